@@ -0,0 +1,53 @@
+use std::time::{Duration, Instant};
+use pacman::common::Level;
+use pacman::level_gen::{GenStep, LevelBuilder, MazeConfig, MazeKind};
+use rand::{SeedableRng, rngs::StdRng};
+
+// native timing of the maze generators, run with `cargo bench`
+const SIZES: [(u32, u32); 5] = [(31, 31), (61, 61), (101, 101), (151, 151), (201, 201)];
+const KINDS: [MazeKind; 4] = [MazeKind::Classic, MazeKind::Braided, MazeKind::Rooms, MazeKind::Wfc];
+const RUNS: u32 = 10;
+// the longest a single LevelBuilder::step may take, so a maze can be built a frame at a time
+const STEP_BUDGET: Duration = Duration::from_millis(8);
+// a whole classic maze of the largest size has to stay under 50ms in release wasm,
+// which runs up to about twice as slow as native code
+const CLASSIC_TARGET: Duration = Duration::from_millis(25);
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(0);
+    for kind in KINDS {
+        for (width, height) in SIZES {
+            let mut total = Duration::ZERO;
+            let mut worst = Duration::ZERO;
+            for _ in 0..RUNS {
+                let start = Instant::now();
+                let level = Level::generate(kind.generator(), &mut rng, &MazeConfig::new(width, height, 0)).expect("valid size");
+                let elapsed = start.elapsed();
+                assert!(level.dot_count() > 0);
+                total += elapsed;
+                worst = worst.max(elapsed);
+            }
+            println!("{:<8} {:>3}x{:<3}  mean {:>10.3?}  worst {:>10.3?}", format!("{:?}", kind), width, height, total / RUNS, worst);
+            if kind == MazeKind::Classic && (width, height) == SIZES[SIZES.len() - 1] {
+                assert!(total / RUNS < CLASSIC_TARGET, "a {}x{} classic maze took {:?}", width, height, total / RUNS);
+            }
+        }
+    }
+
+    let (width, height) = SIZES[SIZES.len() - 1];
+    for kind in KINDS {
+        let mut worst = (Duration::ZERO, GenStep::Done);
+        for seed in 0..RUNS as u64 {
+            let mut builder = LevelBuilder::new(kind, &MazeConfig::new(width, height, 0), StdRng::seed_from_u64(seed)).expect("valid size");
+            loop {
+                let start = Instant::now();
+                let step = builder.step().expect("valid maze");
+                let elapsed = start.elapsed();
+                if step == GenStep::Done { break; }
+                if elapsed > worst.0 { worst = (elapsed, step); }
+            }
+        }
+        println!("{:<8} {:>3}x{:<3}  worst step {:>10.3?} ({:?})", format!("{:?}", kind), width, height, worst.0, worst.1);
+        assert!(worst.0 < STEP_BUDGET, "{:?} took {:?} for one {:?} step", kind, worst.0, worst.1);
+    }
+}
@@ -0,0 +1,34 @@
+use std::time::{Duration, Instant};
+use pacman::Game;
+use pacman::common::DifficultyProfile;
+use pacman::config::GameConfig;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+// native timing of Game::tick on large mazes, run with `cargo bench`
+const SIZES: [u32; 2] = [101, 201];
+const TICKS: u32 = 3000;
+const KEYS: [&str; 4] = ["ArrowRight", "ArrowDown", "ArrowLeft", "ArrowUp"];
+
+fn main() {
+    let profiles = [("greedy", DifficultyProfile::normal()), ("personal", DifficultyProfile::expert()), ("pathfind", DifficultyProfile::nightmare())];
+    for (name, diff) in profiles {
+        for size in SIZES {
+            let config = GameConfig::new(size, size, diff).seed(0).lives(255).timers(0, 0, 0);
+            let mut game = Game::from_config(config).expect("valid config");
+            let mut rng = StdRng::seed_from_u64(0);
+            let (mut total, mut worst) = (Duration::ZERO, Duration::ZERO);
+            for tick in 0..TICKS {
+                // pacman turns every few ticks so he keeps changing tiles
+                if tick % 8 == 0 { game.set_dir(KEYS[rng.gen_range(0..KEYS.len())]); }
+                let start = Instant::now();
+                let over = game.tick().expect("tick");
+                let elapsed = start.elapsed();
+                total += elapsed;
+                worst = worst.max(elapsed);
+                game.drain_events();
+                if over { break; }
+            }
+            println!("{:<8} {:>3}x{:<3}  mean {:>10.3?}  worst {:>10.3?}", name, size, size, total / TICKS, worst);
+        }
+    }
+}
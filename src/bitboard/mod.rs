@@ -0,0 +1,377 @@
+use crate::common::{Cord, Dir};
+use crate::grid::{Grid, Rect};
+use crate::movement::DIRS;
+
+const BITS: usize = u64::BITS as usize;
+
+// one bit per tile in the row major order of Grid, the bits past width * height are always 0
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct BitBoard {
+    width: u32,
+    height: u32,
+    words: Vec<u64>,
+}
+
+impl BitBoard {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height, words: vec![0; (width as usize * height as usize).div_ceil(BITS)] }
+    }
+
+    pub fn from_grid<T>(grid: &Grid<T>, pred: impl Fn(&T) -> bool) -> Self {
+        let mut board = Self::new(grid.width(), grid.height());
+        for (idx, cell) in grid.as_slice().iter().enumerate() {
+            if pred(cell) { board.words[idx / BITS] |= 1 << (idx % BITS); }
+        }
+        board
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn len(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+    fn idx(&self, cord: Cord) -> Option<usize> {
+        if cord.0 >= self.height || cord.1 >= self.width { return None; }
+        Some(cord.0 as usize * self.width as usize + cord.1 as usize)
+    }
+    fn to_cord(&self, idx: usize) -> Cord {
+        Cord((idx / self.width as usize) as u32, (idx % self.width as usize) as u32)
+    }
+
+    // false outside of the board
+    pub fn get(&self, cord: Cord) -> bool {
+        self.idx(cord).is_some_and(|idx| self.words[idx / BITS] & (1 << (idx % BITS)) != 0)
+    }
+    pub fn set(&mut self, cord: Cord, value: bool) {
+        let Some(idx) = self.idx(cord) else { return; };
+        let (word, bit) = (&mut self.words[idx / BITS], 1 << (idx % BITS));
+        if value { *word |= bit; } else { *word &= !bit; }
+    }
+
+    pub fn clear(&mut self) {
+        self.words.fill(0);
+    }
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|word| *word == 0)
+    }
+    pub fn count(&self) -> u32 {
+        self.words.iter().map(|word| word.count_ones()).sum()
+    }
+
+    // number of set tiles inside `region`, the part outside of the board is ignored
+    pub fn count_in(&self, region: Rect) -> u32 {
+        let region = region.clip(self.width, self.height);
+        let (Cord(top, left), end) = (region.top_left, region.end());
+        let row = |y: u32| y as usize * self.width as usize;
+        (top..end.0)
+            .map(|y| self.count_bits(row(y) + left as usize, row(y) + end.1 as usize))
+            .sum()
+    }
+
+    // popcount of the bit indices start..end, a word at a time
+    fn count_bits(&self, start: usize, end: usize) -> u32 {
+        let mut count = 0;
+        let mut idx = start;
+        while idx < end {
+            let bit = idx % BITS;
+            let take = (BITS - bit).min(end - idx);
+            let mask = if take == BITS { !0 } else { ((1u64 << take) - 1) << bit };
+            count += (self.words[idx / BITS] & mask).count_ones();
+            idx += take;
+        }
+        count
+    }
+
+    // set tiles in row major order
+    pub fn ones(&self) -> impl Iterator<Item = Cord> + '_ {
+        self.words.iter().enumerate().flat_map(move |(i, word)| {
+            std::iter::successors(Some(*word).filter(|w| *w != 0), |w| Some(w & (w - 1)).filter(|w| *w != 0))
+                .map(move |w| self.to_cord(i * BITS + w.trailing_zeros() as usize))
+        })
+    }
+    pub fn first(&self) -> Option<Cord> {
+        self.ones().next()
+    }
+    // the first tile set in both boards, without building the intersection
+    pub fn first_common(&self, other: &BitBoard) -> Option<Cord> {
+        self.words.iter().zip(&other.words).enumerate()
+            .find(|(_, (a, b))| *a & *b != 0)
+            .map(|(i, (a, b))| self.to_cord(i * BITS + (a & b).trailing_zeros() as usize))
+    }
+
+    // the set operations expect boards of the same size
+    pub fn and(&mut self, other: &BitBoard) {
+        self.words.iter_mut().zip(&other.words).for_each(|(a, b)| *a &= b);
+    }
+    pub fn or(&mut self, other: &BitBoard) {
+        self.words.iter_mut().zip(&other.words).for_each(|(a, b)| *a |= b);
+    }
+    pub fn and_not(&mut self, other: &BitBoard) {
+        self.words.iter_mut().zip(&other.words).for_each(|(a, b)| *a &= !b);
+    }
+    pub fn invert(&mut self) {
+        self.words.iter_mut().for_each(|word| *word = !*word);
+        self.clear_tail();
+    }
+
+    // every tile moved one step in `dir` into `out`, tiles leaving the board are dropped (tunnels aren't followed)
+    pub fn shift_into(&self, dir: Dir, out: &mut BitBoard) {
+        out.width = self.width;
+        out.height = self.height;
+        out.words.resize(self.words.len(), 0);
+        let width = self.width as usize;
+        match dir {
+            Dir::Right => {
+                shift_up(&self.words, 1, &mut out.words);
+                out.clear_col(0);
+            }
+            Dir::Left => {
+                shift_down(&self.words, 1, &mut out.words);
+                out.clear_col(self.width.saturating_sub(1));
+            }
+            Dir::Down => shift_up(&self.words, width, &mut out.words),
+            Dir::Up => shift_down(&self.words, width, &mut out.words),
+            Dir::None => out.words.copy_from_slice(&self.words),
+        }
+        out.clear_tail();
+    }
+    pub fn shift(&self, dir: Dir) -> BitBoard {
+        let mut out = BitBoard::default();
+        self.shift_into(dir, &mut out);
+        out
+    }
+
+    // one flood fill step: the tiles of `self` and their neighbours, limited to `walkable`
+    pub fn grow_into(&self, walkable: &BitBoard, scratch: &mut BitBoard, out: &mut BitBoard) {
+        out.clone_from(self);
+        for dir in DIRS {
+            self.shift_into(dir, scratch);
+            out.or(scratch);
+        }
+        out.and(walkable);
+    }
+
+    // every walkable tile connected to `from`, `self` is the walkable mask
+    pub fn flood_fill(&self, from: Cord) -> BitBoard {
+        let mut reach = BitBoard::new(self.width, self.height);
+        reach.set(from, self.get(from));
+        let (mut next, mut scratch) = (BitBoard::default(), BitBoard::default());
+        loop {
+            reach.grow_into(self, &mut scratch, &mut next);
+            if next == reach { return reach; }
+            std::mem::swap(&mut reach, &mut next);
+        }
+    }
+
+    // the closest target reachable from `from` and its distance in steps, `self` is the walkable mask,
+    // ties go to the first tile in row major order
+    pub fn nearest(&self, from: Cord, targets: &BitBoard) -> Option<(Cord, u32)> {
+        let mut reach = BitBoard::new(self.width, self.height);
+        reach.set(from, self.get(from));
+        let (mut next, mut scratch) = (BitBoard::default(), BitBoard::default());
+        let mut dist = 0;
+        loop {
+            if let Some(cord) = reach.first_common(targets) { return Some((cord, dist)); }
+            reach.grow_into(self, &mut scratch, &mut next);
+            if next == reach { return None; }
+            std::mem::swap(&mut reach, &mut next);
+            dist += 1;
+        }
+    }
+
+    fn clear_col(&mut self, x: u32) {
+        for y in 0..self.height {
+            let idx = y as usize * self.width as usize + x as usize;
+            self.words[idx / BITS] &= !(1 << (idx % BITS));
+        }
+    }
+    fn clear_tail(&mut self) {
+        let (len, bit) = (self.len(), self.len() % BITS);
+        if let Some(last) = self.words.last_mut().filter(|_| bit != 0 && len > 0) { *last &= (1 << bit) - 1; }
+    }
+}
+
+// moves every bit `n` indices towards the end of the board
+fn shift_up(src: &[u64], n: usize, out: &mut [u64]) {
+    let (words, bits) = (n / BITS, n % BITS);
+    for (i, word) in out.iter_mut().enumerate() {
+        let low = i.checked_sub(words).map_or(0, |j| src[j]);
+        let carry = i.checked_sub(words + 1).filter(|_| bits != 0).map_or(0, |j| src[j] >> (BITS - bits));
+        *word = if bits == 0 { low } else { (low << bits) | carry };
+    }
+}
+
+// moves every bit `n` indices towards the start of the board
+fn shift_down(src: &[u64], n: usize, out: &mut [u64]) {
+    let (words, bits) = (n / BITS, n % BITS);
+    for (i, word) in out.iter_mut().enumerate() {
+        let high = src.get(i + words).copied().unwrap_or(0);
+        let carry = src.get(i + words + 1).filter(|_| bits != 0).map_or(0, |w| w << (BITS - bits));
+        *word = (high >> bits) | carry;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+    use crate::common::{BlockType, Level};
+
+    const SIZES: [(u32, u32); 7] = [(63, 5), (64, 5), (65, 5), (1, 9), (9, 1), (130, 3), (7, 7)];
+
+    fn random_grid(rng: &mut StdRng, width: u32, height: u32, percent: u32) -> Grid<bool> {
+        let cells = (0..width * height).map(|_| rng.gen_range(0..100) < percent).collect();
+        Grid::from_vec(width, height, cells).unwrap()
+    }
+
+    // steps from `from` to every tile of `walkable`, None where it can't be reached
+    fn bfs(walkable: &Grid<bool>, from: Cord) -> Grid<Option<u32>> {
+        let mut dist = Grid::new(walkable.width(), walkable.height(), None);
+        if !walkable[from] { return dist; }
+        dist[from] = Some(0);
+        let mut queue = VecDeque::from([from]);
+        while let Some(cord) = queue.pop_front() {
+            for (_, next) in walkable.neighbors(cord) {
+                if walkable[next] && dist[next].is_none() {
+                    dist[next] = dist[cord].map(|d| d + 1);
+                    queue.push_back(next);
+                }
+            }
+        }
+        dist
+    }
+
+    fn nearest_by_bfs(walkable: &Grid<bool>, from: Cord, targets: &Grid<bool>) -> Option<(Cord, u32)> {
+        let dist = bfs(walkable, from);
+        // min_by_key keeps the first of equal distances, i.e. row major order
+        dist.iter()
+            .filter_map(|(cord, d)| d.filter(|_| targets[cord]).map(|d| (cord, d)))
+            .min_by_key(|(_, d)| *d)
+    }
+
+    #[test]
+    fn words_shift_across_their_boundaries() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let src: Vec<u64> = (0..4).map(|_| rng.gen()).collect();
+        let bit = |words: &[u64], i: usize| words[i / BITS] >> (i % BITS) & 1 == 1;
+        for n in [0, 1, 63, 64, 65, 127, 130] {
+            let (mut up, mut down) = (vec![0; 4], vec![0; 4]);
+            shift_up(&src, n, &mut up);
+            shift_down(&src, n, &mut down);
+            for i in 0..4 * BITS {
+                assert_eq!(bit(&up, i), i >= n && bit(&src, i - n), "up {} bit {}", n, i);
+                assert_eq!(bit(&down, i), i + n < 4 * BITS && bit(&src, i + n), "down {} bit {}", n, i);
+            }
+        }
+    }
+
+    #[test]
+    fn shifts_match_moving_every_tile() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for (width, height) in SIZES {
+            let grid = random_grid(&mut rng, width, height, 50);
+            let board = BitBoard::from_grid(&grid, |b| *b);
+            for dir in DIRS.iter().copied().chain([Dir::None]) {
+                let mut expected = BitBoard::new(width, height);
+                for (cord, _) in grid.iter().filter(|(_, b)| **b) {
+                    let next = if dir == Dir::None { Some(cord) } else { grid.neighbor(cord, dir) };
+                    if let Some(next) = next { expected.set(next, true); }
+                }
+                assert_eq!(board.shift(dir), expected, "{}x{}", width, height);
+            }
+        }
+    }
+
+    #[test]
+    fn invert_keeps_the_tail_clear() {
+        for (width, height) in SIZES {
+            let mut board = BitBoard::new(width, height);
+            board.invert();
+            assert_eq!(board.count(), width * height);
+            assert_eq!(board.ones().count() as u32, width * height);
+        }
+    }
+
+    #[test]
+    fn count_in_clips_regions_at_the_edges() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for (width, height) in SIZES {
+            let grid = random_grid(&mut rng, width, height, 50);
+            let board = BitBoard::from_grid(&grid, |b| *b);
+            for _ in 0..50 {
+                let top_left = Cord(rng.gen_range(0..height + 2), rng.gen_range(0..width + 2));
+                let region = Rect::new(top_left, rng.gen_range(0..height + 3), rng.gen_range(0..width + 70));
+                let expected = region.cords().filter(|cord| grid.get(*cord) == Some(&true)).count() as u32;
+                assert_eq!(board.count_in(region), expected, "{}x{} {:?}", width, height, region);
+            }
+            assert_eq!(board.count_in(Rect::new(Cord(0, 0), height, width)), board.count());
+            // no rows or no columns is nothing, also at the origin
+            let full = BitBoard::from_grid(&grid, |_| true);
+            assert_eq!(full.count_in(Rect::new(Cord(0, 0), 0, width)), 0);
+            assert_eq!(full.count_in(Rect::new(Cord(0, 0), height, 0)), 0);
+        }
+    }
+
+    #[test]
+    fn first_common_is_the_first_tile_of_the_intersection() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for (width, height) in SIZES {
+            let (a, b) = (random_grid(&mut rng, width, height, 10), random_grid(&mut rng, width, height, 10));
+            let (board_a, board_b) = (BitBoard::from_grid(&a, |x| *x), BitBoard::from_grid(&b, |x| *x));
+            let expected = a.cords().find(|cord| a[*cord] && b[*cord]);
+            assert_eq!(board_a.first_common(&board_b), expected);
+        }
+    }
+
+    #[test]
+    fn flood_fill_and_nearest_match_a_bfs() {
+        let mut rng = StdRng::seed_from_u64(4);
+        for (width, height) in SIZES {
+            for _ in 0..10 {
+                let walkable = random_grid(&mut rng, width, height, 65);
+                let targets = random_grid(&mut rng, width, height, 5);
+                let mask = BitBoard::from_grid(&walkable, |b| *b);
+                let from = Cord(rng.gen_range(0..height), rng.gen_range(0..width));
+                let dist = bfs(&walkable, from);
+                let expected = BitBoard::from_grid(&dist, |d| d.is_some());
+                assert_eq!(mask.flood_fill(from), expected);
+                let target_board = BitBoard::from_grid(&targets, |b| *b);
+                assert_eq!(mask.nearest(from, &target_board), nearest_by_bfs(&walkable, from, &targets));
+            }
+        }
+    }
+
+    #[test]
+    fn levels_count_and_find_their_dots() {
+        let mut rng = StdRng::seed_from_u64(5);
+        for (width, height) in [(13, 11), (63, 15), (65, 21)] {
+            let mut level = Level::new(width, height, 0).unwrap();
+            // eat some dots so the nearest one isn't always next to the spawn
+            let dots: Vec<Cord> = level.grid().iter().filter(|(_, b)| **b == BlockType::PacDot).map(|(c, _)| c).collect();
+            for cord in dots.iter().filter(|_| rng.gen_range(0..100) < 80) { level.set_block(*cord, BlockType::Empty); }
+
+            let is_dot = |b: &BlockType| matches!(b, BlockType::PacDot | BlockType::PowerPellet);
+            for _ in 0..20 {
+                let top_left = Cord(rng.gen_range(0..height), rng.gen_range(0..width));
+                let region = Rect::new(top_left, rng.gen_range(0..height), rng.gen_range(0..width));
+                let expected = region.cords().filter(|c| level.grid().get(*c).is_some_and(is_dot)).count() as u32;
+                assert_eq!(level.dots_in(region), expected);
+            }
+            let first = level.dots().first().unwrap();
+            assert_eq!(level.dots_in(Rect::new(first, 0, 0)), 0);
+
+            let walkable = Grid::from_vec(width, height, level.grid().iter()
+                .map(|(c, b)| *b != BlockType::Wall && !level.meta().gates.contains(&c)).collect()).unwrap();
+            let targets = Grid::from_vec(width, height, level.grid().as_slice().iter().map(is_dot).collect()).unwrap();
+            for from in level.grid().cords().filter(|c| walkable[*c]) {
+                assert_eq!(level.nearest_dot(from), nearest_by_bfs(&walkable, from, &targets));
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use crate::common::{Entity, Level};
+use crate::movement::{self, COLLISION_DISTANCE};
+
+// a ghost that touched pacman during the tick
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct Contact {
+    pub ghost: usize,
+    pub id: u32,
+    pub vulnerable: bool,
+}
+
+#[derive(Clone, Eq, PartialEq, Default)]
+pub struct Resolution {
+    // indices of the eaten ghosts, ordered by ghost id
+    pub eaten: Vec<usize>,
+    pub pacman_dies: bool,
+}
+
+// overlapping at the end of the tick or passed through each other during it
+pub fn contact(level: &Level, ghost: &Entity, pacman: &Entity) -> bool {
+    movement::touching(level, ghost, pacman) || crossed(ghost, pacman)
+}
+
+fn crossed(a: &Entity, b: &Entity) -> bool {
+    let (pa, pb, prev_a, prev_b) = (a.pos(), b.pos(), a.prev_pos(), b.prev_pos());
+    let before = (prev_a.0 - prev_b.0, prev_a.1 - prev_b.1);
+    let after = (pa.0 - pb.0, pa.1 - pb.1);
+    // a jump through a tunnel is no crossing
+    let close = before.0.abs().max(before.1.abs()) < 4 * COLLISION_DISTANCE;
+    let swapped_y = before.0 * after.0 < 0 && before.1.abs() < COLLISION_DISTANCE && after.1.abs() < COLLISION_DISTANCE;
+    let swapped_x = before.1 * after.1 < 0 && before.0.abs() < COLLISION_DISTANCE && after.0.abs() < COLLISION_DISTANCE;
+    close && (swapped_y || swapped_x)
+}
+
+// eating wins over dying: every frightened ghost in contact is eaten (in id order, which decides
+// the points), afterwards any other ghost in contact still kills pacman
+pub fn resolve(contacts: &[Contact]) -> Resolution {
+    let mut contacts = contacts.to_vec();
+    contacts.sort_by_key(|contact| contact.id);
+    Resolution {
+        eaten: contacts.iter().filter(|c| c.vulnerable).map(|c| c.ghost).collect(),
+        pacman_dies: contacts.iter().any(|c| !c.vulnerable),
+    }
+}
+
+// every ghost eaten during one frightened phase doubles the points, up to the fourth
+pub fn ghost_points(base: u32, combo: u32) -> u32 {
+    base << combo.min(3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{Cord, SUBTILES};
+
+    fn moved(from: Cord, to: Cord) -> Entity {
+        let mut entity = Entity::new(to, 1);
+        entity.prev_cord = from;
+        entity
+    }
+
+    #[test]
+    fn crossing_in_one_tick_is_a_contact() {
+        let level = Level::new(13, 11, 0).unwrap();
+        let pacman = moved(Cord(5, 5), Cord(5, 6));
+        let ghost = moved(Cord(5, 6), Cord(5, 5));
+        assert!(!movement::touching(&level, &ghost, &pacman));
+        assert!(contact(&level, &ghost, &pacman));
+        assert!(contact(&level, &pacman, &ghost));
+
+        // following each other is no crossing
+        let behind = moved(Cord(5, 4), Cord(5, 5));
+        assert!(!contact(&level, &behind, &pacman));
+
+        // moving past each other in neighbouring rows neither
+        let beside = moved(Cord(4, 6), Cord(4, 5));
+        assert!(!contact(&level, &beside, &pacman));
+
+        // an offset left from cornering still crosses
+        let mut cornering = moved(Cord(5, 6), Cord(5, 5));
+        cornering.sub = (1 - SUBTILES / 2, 0);
+        cornering.prev_sub = cornering.sub;
+        assert!(contact(&level, &cornering, &pacman));
+    }
+
+    #[test]
+    fn a_tunnel_jump_is_no_crossing() {
+        let level = Level::new(13, 11, 0).unwrap();
+        let pacman = moved(Cord(5, 0), Cord(5, 12));
+        let ghost = moved(Cord(5, 10), Cord(5, 1));
+        assert!(!contact(&level, &ghost, &pacman));
+    }
+
+    #[test]
+    fn frightened_ghosts_are_eaten_before_pacman_dies() {
+        let contacts = [
+            Contact { ghost: 2, id: 3, vulnerable: true },
+            Contact { ghost: 0, id: 1, vulnerable: false },
+            Contact { ghost: 1, id: 0, vulnerable: true },
+        ];
+        let resolution = resolve(&contacts);
+        assert_eq!(resolution.eaten, vec![1, 2]);
+        assert!(resolution.pacman_dies);
+
+        let frightened: Vec<Contact> = contacts.iter().copied().filter(|c| c.vulnerable).collect();
+        assert!(resolve(&frightened) == Resolution { eaten: vec![1, 2], pacman_dies: false });
+        assert!(resolve(&[]) == Resolution::default());
+    }
+
+    #[test]
+    fn ghost_points_double_and_cap() {
+        let points: Vec<u32> = (0..6).map(|combo| ghost_points(200, combo)).collect();
+        assert_eq!(points, vec![200, 400, 800, 1600, 1600, 1600]);
+    }
+}
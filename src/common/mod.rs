@@ -1,5 +1,9 @@
 use wasm_bindgen::prelude::wasm_bindgen;
-use crate::level_gen;
+use crate::level_gen::{Classic, LevelMeta, MazeConfig, MazeGenerator};
+use crate::error::PacmanError;
+use crate::grid::{Grid, Rect};
+use crate::bitboard::BitBoard;
+use rand::{thread_rng, RngCore};
 extern crate web_sys;
 
 #[macro_export]
@@ -11,7 +15,7 @@ macro_rules! log {
 
 #[wasm_bindgen]
 #[repr(u8)]
-#[derive(Clone, Copy, Eq, PartialEq)]
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
 pub enum BlockType {
     Empty = 0,
     Wall = 1,
@@ -23,10 +27,82 @@ pub enum BlockType {
 #[wasm_bindgen]
 #[derive(Clone, Copy, Eq, PartialEq)]
 #[repr(u8)]
-pub enum Difficulty {
-    Normal = 0,
-    Expert = 1,
+pub enum Phase {
+    Ready = 0,
+    Playing = 1,
+    Dying = 2,
+    LevelComplete = 3,
+    GameOver = 4,
 }
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Eq, PartialEq)]
+#[repr(u8)]
+pub enum GhostAi {
+    // ghosts only roam towards their corners, they never hunt pacman
+    Wander = 0,
+    // ghosts head straight for pacman's tile
+    Greedy = 1,
+    // every ghost picks its own target around pacman (see Ghost::chase_target)
+    Personalities = 2,
+    // ghosts follow the shortest path to pacman and frightened ghosts flee from him
+    Pathfinding = 3,
+}
+
+// speeds are a percentage of FULL_SPEED at the first level, durations are in ticks
+#[wasm_bindgen]
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct DifficultyProfile {
+    pub ghost_speed: u8,
+    pub pacman_speed: u8,
+    pub frightened_time: u32,
+    pub scatter_time: u32,
+    pub chase_time: u32,
+    pub ghost_ai: GhostAi,
+    pub lives: u8,
+    pub ghost_count: u32,
+}
+
+#[wasm_bindgen]
+impl DifficultyProfile {
+    #[wasm_bindgen(constructor)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(ghost_speed: u8, pacman_speed: u8, frightened_time: u32, scatter_time: u32, chase_time: u32,
+               ghost_ai: GhostAi, lives: u8, ghost_count: u32) -> Self {
+        Self {
+            ghost_speed: ghost_speed.clamp(1, MAX_SPEED),
+            pacman_speed: pacman_speed.clamp(1, MAX_SPEED),
+            frightened_time,
+            scatter_time,
+            chase_time,
+            ghost_ai,
+            lives: std::cmp::max(lives, 1),
+            ghost_count,
+        }
+    }
+    pub fn easy() -> Self {
+        Self::new(60, 90, 8 * TICKS_PER_SECOND, 9 * TICKS_PER_SECOND, 15 * TICKS_PER_SECOND, GhostAi::Wander, 5, 3)
+    }
+    pub fn normal() -> Self {
+        Self::new(75, 80, 4 * TICKS_PER_SECOND, 7 * TICKS_PER_SECOND, 20 * TICKS_PER_SECOND, GhostAi::Greedy, 3, 4)
+    }
+    pub fn expert() -> Self {
+        Self::new(90, 90, 2 * TICKS_PER_SECOND, 5 * TICKS_PER_SECOND, 25 * TICKS_PER_SECOND, GhostAi::Personalities, 3, 4)
+    }
+    pub fn nightmare() -> Self {
+        Self::new(100, 95, TICKS_PER_SECOND / 2, TICKS_PER_SECOND, 30 * TICKS_PER_SECOND, GhostAi::Pathfinding, 1, 6)
+    }
+}
+
+// speeds are given in percent of FULL_SPEED, which covers TILES_PER_SECOND
+pub const FULL_SPEED: u8 = 100;
+pub const MAX_SPEED: u8 = 200;
+pub const TILES_PER_SECOND: u32 = 10;
+// rate at which the frontend calls Game::tick
+pub const TICKS_PER_SECOND: u32 = 60;
+// resolution of the positions inside a tile
+pub const SUBTILES: i32 = 16;
+
 #[wasm_bindgen]
 #[derive(Clone, Copy, Eq, PartialEq)]
 #[repr(u8)]
@@ -36,6 +112,18 @@ pub enum Color {
     Cyan = 3,
     Orange = 4,
 }
+
+impl Color {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Color::Red),
+            2 => Some(Color::Pink),
+            3 => Some(Color::Cyan),
+            4 => Some(Color::Orange),
+            _ => None,
+        }
+    }
+}
 #[derive(Clone, Copy, Eq, PartialEq)]
 pub struct Entity {
     pub cord: Cord,
@@ -44,17 +132,70 @@ pub struct Entity {
     pub dir: Dir,
     pub prev_dir: Dir,
     pub tick: u64,
+    pub speed: u8,
+    // fixed-point offset from the centre of `cord` in 1/SUBTILES of a tile
+    pub sub: (i32, i32),
+    pub prev_sub: (i32, i32),
+    progress: u32,
+}
+
+impl Entity {
+    pub fn new(start_cord: Cord, speed: u8) -> Self {
+        Self {
+            cord: start_cord,
+            prev_cord: start_cord,
+            start_cord,
+            dir: Dir::None,
+            prev_dir: Dir::None,
+            tick: 0,
+            speed: speed.clamp(1, MAX_SPEED),
+            sub: (0, 0),
+            prev_sub: (0, 0),
+            progress: 0,
+        }
+    }
+
+    // accumulates the speed and returns the sub tile units to move this tick
+    pub fn advance(&mut self) -> u32 {
+        const PER_TICK: u32 = FULL_SPEED as u32 * TICKS_PER_SECOND;
+        self.progress += self.speed as u32 * SUBTILES as u32 * TILES_PER_SECOND;
+        let units = self.progress / PER_TICK;
+        self.progress %= PER_TICK;
+        units
+    }
+
+    // remembers where the entity was before it moves this tick
+    pub fn begin_tick(&mut self) {
+        self.prev_cord = self.cord;
+        self.prev_sub = self.sub;
+    }
+
+    // position in sub tile units
+    pub fn pos(&self) -> (i64, i64) {
+        (self.cord.0 as i64 * SUBTILES as i64 + self.sub.0 as i64, self.cord.1 as i64 * SUBTILES as i64 + self.sub.1 as i64)
+    }
+    pub fn prev_pos(&self) -> (i64, i64) {
+        (self.prev_cord.0 as i64 * SUBTILES as i64 + self.prev_sub.0 as i64, self.prev_cord.1 as i64 * SUBTILES as i64 + self.prev_sub.1 as i64)
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.start_cord, self.speed);
+    }
 }
 
 #[wasm_bindgen]
-#[derive(Clone, Copy, Eq, PartialEq)]
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
 #[repr(C)]
 pub struct Cord(pub u32, pub u32);
 
- impl std::ops::Add<(i32, i32)> for Cord {
-    type Output = Self;
-    fn add(self, rhs: (i32, i32)) -> Self::Output {
-        Self((self.0 as i32 + rhs.0) as u32, (self.1 as i32 + rhs.1) as u32)
+impl Cord {
+    // None if the offset leads above or left of the field
+    pub fn checked_add(self, (dy, dx): (i32, i32)) -> Option<Self> {
+        Some(Self(self.0.checked_add_signed(dy)?, self.1.checked_add_signed(dx)?))
+    }
+    // stops at the top and left edge of the field
+    pub fn saturating_add(self, (dy, dx): (i32, i32)) -> Self {
+        Self(self.0.saturating_add_signed(dy), self.1.saturating_add_signed(dx))
     }
 }
 
@@ -77,7 +218,7 @@ pub enum Dir {
 
 impl Dir {
     pub fn from_keycode(key_code: &str) -> Self {
-        return match key_code {
+        match key_code {
             "ArrowRight" | "KeyD" => Dir::Right,
             "ArrowLeft" | "KeyA" => Dir::Left,
             "ArrowUp" | "KeyW" => Dir::Up,
@@ -115,55 +256,137 @@ impl Dir {
 #[derive(Clone, Eq, PartialEq)]
 #[repr(C)]
 pub struct Level {
-    width: u32,
-    height: u32,
     dot_count: u64,
-    field: Vec<BlockType>,
+    total_dots: u64,
+    wall_version: u64,
+    field: Grid<BlockType>,
+    meta: LevelMeta,
+    // packed copies of the field for the AI, kept in sync by set_block
+    walls: BitBoard,
+    dots: BitBoard,
+    pellets: BitBoard,
 }
 
-const MIN_WIDTH: u32 = 13;
-const MIN_HEIGHT: u32 = 11;
+pub const MIN_WIDTH: u32 = 13;
+pub const MIN_HEIGHT: u32 = 11;
 
 impl Level {
-    pub fn new(mut width: u32, mut height: u32) -> Self {
-        width = std::cmp::max(if width % 2 == 0 { width - 1} else {width}, MIN_WIDTH);
-        height = std::cmp::max(if height % 2 == 0 { height - 1} else {height}, MIN_HEIGHT);
+    pub fn new(width: u32, height: u32, tunnels: u32) -> Result<Self, PacmanError> {
+        Self::generate(&Classic, &mut thread_rng(), &MazeConfig::new(width, height, tunnels))
+    }
+
+    // the size of the config is fitted before the generator sees it
+    pub fn generate(generator: &dyn MazeGenerator, rng: &mut dyn RngCore, config: &MazeConfig) -> Result<Self, PacmanError> {
+        let (width, height) = Self::fit_size(config.width, config.height);
+        let (field, meta) = generator.generate(rng, &MazeConfig { width, height, ..*config })?;
+        Ok(Self::from_maze(field, meta))
+    }
 
-        let field = level_gen::generate(width, height);
+    // a finished maze, e.g. from a LevelBuilder
+    pub(crate) fn from_maze(field: Grid<BlockType>, meta: LevelMeta) -> Self {
+        let walls = BitBoard::from_grid(&field, |b| *b == BlockType::Wall);
+        let dots = BitBoard::from_grid(&field, |b| *b == BlockType::PacDot);
+        let pellets = BitBoard::from_grid(&field, |b| *b == BlockType::PowerPellet);
+        let dot_count = (dots.count() + pellets.count()) as u64;
         Self {
-            width,
-            height,
-            dot_count: Self::count_dots(&field),
+            dot_count,
+            total_dots: dot_count,
+            wall_version: 0,
             field,
+            meta,
+            walls,
+            dots,
+            pellets,
         }
     }
 
-    fn count_dots(level: &Vec<BlockType>) -> u64 {
-        level.iter().filter(|t| **t == BlockType::PowerPellet || **t == BlockType::PacDot).count() as u64
+    // the size a level actually gets, mazes need odd dimensions
+    pub fn fit_size(width: u32, height: u32) -> (u32, u32) {
+        let odd = |size: u32| if size % 2 == 0 { size.saturating_sub(1) } else { size };
+        (std::cmp::max(odd(width), MIN_WIDTH), std::cmp::max(odd(height), MIN_HEIGHT))
     }
 
     pub fn to_idx(&self, cord: Cord) -> usize {
-        (cord.0 * self.width + cord.1) as usize
+        self.field.to_idx(cord)
+    }
+
+    pub fn in_bounds(&self, cord: Cord) -> bool {
+        self.field.in_bounds(cord)
+    }
+
+    // the tile next to `cord` in `dir`, None at the edge of the field (see movement::neighbor for tunnels)
+    pub fn neighbor(&self, cord: Cord, dir: Dir) -> Option<Cord> {
+        self.field.neighbor(cord, dir)
+    }
+
+    pub fn neighbors(&self, cord: Cord) -> impl Iterator<Item = (Dir, Cord)> + '_ {
+        self.field.neighbors(cord)
     }
 
     pub fn block(&self, cord: Cord) -> BlockType {
-        let idx = self.to_idx(cord);
-        self.field[idx]
+        self.field[cord]
     }
 
     pub fn set_block(&mut self, cord: Cord, block: BlockType){
-        let idx = self.to_idx(cord);
-        self.field[idx] = block;
+        if (self.field[cord] == BlockType::Wall) != (block == BlockType::Wall) { self.wall_version += 1; }
+        self.field[cord] = block;
+        self.walls.set(cord, block == BlockType::Wall);
+        self.dots.set(cord, block == BlockType::PacDot);
+        self.pellets.set(cord, block == BlockType::PowerPellet);
     }
 
-    pub fn reduce_dot_count(&mut self) {
-        self.dot_count -= 1;
+    pub fn grid(&self) -> &Grid<BlockType> {
+        &self.field
+    }
+
+    pub fn meta(&self) -> &LevelMeta {
+        &self.meta
+    }
+
+    pub fn walls(&self) -> &BitBoard {
+        &self.walls
+    }
+    pub fn dots(&self) -> &BitBoard {
+        &self.dots
+    }
+    pub fn pellets(&self) -> &BitBoard {
+        &self.pellets
+    }
+
+    // the tiles pacman can stand on, the mask for BitBoard::flood_fill and BitBoard::nearest
+    pub fn walkable(&self) -> BitBoard {
+        let mut walkable = self.walls.clone();
+        walkable.invert();
+        for gate in &self.meta.gates { walkable.set(*gate, false); }
+        walkable
+    }
+
+    // dots and pellets left inside `region`
+    pub fn dots_in(&self, region: Rect) -> u32 {
+        self.dots.count_in(region) + self.pellets.count_in(region)
+    }
+
+    // the closest dot or pellet pacman can walk to from `from` and its distance, tunnels aren't taken
+    pub fn nearest_dot(&self, from: Cord) -> Option<(Cord, u32)> {
+        let mut targets = self.dots.clone();
+        targets.or(&self.pellets);
+        self.walkable().nearest(from, &targets)
+    }
+
+    // changes whenever a wall is added or removed
+    pub fn wall_version(&self) -> u64 {
+        self.wall_version
+    }
+
+    pub fn reduce_dot_count(&mut self) -> Result<(), PacmanError> {
+        self.dot_count = self.dot_count.checked_sub(1).ok_or(PacmanError::NoDotsLeft)?;
+        Ok(())
     }
     pub fn height(&self) -> u32 {
-        self.height
+        self.field.height()
     }
     pub fn width(&self) -> u32 {
-        self.width
+        self.field.width()
     }
     pub fn field(&self) -> *const BlockType {
         self.field.as_ptr()
@@ -171,6 +394,49 @@ impl Level {
     pub fn dot_count(&self) -> u64 {
         self.dot_count
     }
+    pub fn total_dots(&self) -> u64 {
+        self.total_dots
+    }
 
 }
 
+
+// a level drawn as text for the tests: '#' wall, '.' dot, 'o' pellet, '-' gate, 'h' inside of the ghost house,
+// 'P' the spawn of pacman, anything else is empty
+#[cfg(test)]
+impl Level {
+    pub(crate) fn from_rows(rows: &[&str]) -> Self {
+        let (height, width) = (rows.len() as u32, rows[0].len() as u32);
+        let mut field = Grid::new(width, height, BlockType::Empty);
+        let mut meta = LevelMeta {
+            pacman_spawn: Cord(1, 1),
+            ghost_spawn: Cord(1, 1),
+            ghost_house: Rect::new(Cord(1, 1), 0, 0),
+            house_tiles: Vec::new(),
+            gates: Vec::new(),
+            pellets: Vec::new(),
+            fruit: Cord(1, 1),
+            scatter_corners: [Cord(1, 1), Cord(1, width - 2), Cord(height - 2, width - 2), Cord(height - 2, 1)],
+        };
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let cord = Cord(y as u32, x as u32);
+                field[cord] = match c {
+                    '#' => BlockType::Wall,
+                    '.' => BlockType::PacDot,
+                    'o' => { meta.pellets.push(cord); BlockType::PowerPellet }
+                    '-' => { meta.gates.push(cord); BlockType::Gate }
+                    _ => BlockType::Empty,
+                };
+                if c == 'h' { meta.house_tiles.push(cord); }
+                if c == 'P' { meta.pacman_spawn = cord; meta.fruit = cord; }
+            }
+        }
+        // the house is a rectangle, its walls lie around the first and last house tile
+        if let (Some(first), Some(last)) = (meta.house_tiles.first(), meta.house_tiles.last()) {
+            meta.ghost_house = Rect::from_corners(Cord(first.0 - 1, first.1 - 1), Cord(last.0 + 1, last.1 + 1));
+        }
+        if let Some(gate) = meta.gates.first() { meta.ghost_spawn = Cord(gate.0 + 1, gate.1); }
+        Self::from_maze(field, meta)
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt;
+use wasm_bindgen::prelude::wasm_bindgen;
+use crate::common::{Color, Cord, DifficultyProfile, Level, TICKS_PER_SECOND};
+use crate::level_gen::{self, MazeConfig, MazeKind};
+
+pub const MAX_GHOSTS: usize = 16;
+pub const MAX_SIZE: u32 = 1001;
+
+// everything a game variant can change without forking the crate, built from JS by chaining
+// e.g. `new GameConfig(25, 25, DifficultyProfile.normal()).lives(5).ghost_types([1, 1, 2])`
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct GameConfig {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) tunnels: u32,
+    pub(crate) diff: DifficultyProfile,
+    pub(crate) lives: u8,
+    pub(crate) ghost_types: Vec<u8>,
+    pub(crate) pacman_spawn: Option<Cord>,
+    pub(crate) score_dot: u32,
+    pub(crate) score_pellet: u32,
+    pub(crate) score_ghost: u32,
+    // 0 disables extra lives
+    pub(crate) score_extra_life: u32,
+    pub(crate) ready_time: u32,
+    pub(crate) dying_time: u32,
+    pub(crate) level_complete_time: u32,
+    pub(crate) turn_buffer: u32,
+    // MazeKind values, level n is built by entry (n - 1) % len
+    pub(crate) mazes: Vec<u8>,
+    // None seeds the generators from the system rng
+    pub(crate) seed: Option<u64>,
+    // ASCII sample mazes for MazeKind::Wfc, None uses the arcade maze
+    pub(crate) maze_samples: Option<String>,
+    // percent of the dead ends braiding leaves in a maze
+    pub(crate) dead_ends: u32,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ConfigError {
+    // smaller sizes are raised to the minimum, only sizes above MAX_SIZE are rejected
+    InvalidSize { width: u32, height: u32 },
+    NoLives,
+    TooManyGhosts(usize),
+    UnknownGhostType(u8),
+    TooManyTunnels(u32),
+    SpawnOutOfBounds(Cord),
+    SpawnBlocked(Cord),
+    SpawnInGhostHouse(Cord),
+    SpawnUnreachable(Cord),
+    NoMazes,
+    UnknownMazeKind(u8),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::InvalidSize { width, height } =>
+                write!(f, "invalid maze size {}x{}, it can be at most {}x{}", width, height, MAX_SIZE, MAX_SIZE),
+            ConfigError::NoLives => write!(f, "pacman needs at least one life"),
+            ConfigError::TooManyGhosts(n) => write!(f, "{} ghosts requested, at most {} are supported", n, MAX_GHOSTS),
+            ConfigError::UnknownGhostType(t) => write!(f, "unknown ghost type {}", t),
+            ConfigError::TooManyTunnels(n) => write!(f, "{} tunnels don't fit into the maze", n),
+            ConfigError::SpawnOutOfBounds(c) => write!(f, "pacman spawn ({}, {}) is outside of the maze", c.0, c.1),
+            ConfigError::SpawnBlocked(c) => write!(f, "pacman spawn ({}, {}) is not walkable", c.0, c.1),
+            ConfigError::SpawnInGhostHouse(c) => write!(f, "pacman spawn ({}, {}) is inside the ghost house", c.0, c.1),
+            ConfigError::SpawnUnreachable(c) => write!(f, "pacman spawn ({}, {}) is cut off from the dots", c.0, c.1),
+            ConfigError::NoMazes => write!(f, "at least one maze kind is needed"),
+            ConfigError::UnknownMazeKind(k) => write!(f, "unknown maze kind {}", k),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self::new(25, 25, DifficultyProfile::normal())
+    }
+}
+
+#[wasm_bindgen]
+impl GameConfig {
+    #[wasm_bindgen(constructor)]
+    pub fn new(width: u32, height: u32, diff: DifficultyProfile) -> Self {
+        Self {
+            width,
+            height,
+            tunnels: 0,
+            diff,
+            lives: diff.lives,
+            ghost_types: Self::default_ghosts(diff.ghost_count),
+            pacman_spawn: None,
+            score_dot: 10,
+            score_pellet: 50,
+            score_ghost: 200,
+            score_extra_life: 10_000,
+            ready_time: 2 * TICKS_PER_SECOND,
+            dying_time: 3 * TICKS_PER_SECOND / 2,
+            level_complete_time: 2 * TICKS_PER_SECOND,
+            turn_buffer: 0,
+            // Wfc takes too long on large mazes to run between two levels, it has to be picked with `mazes`
+            mazes: vec![MazeKind::Classic as u8, MazeKind::Braided as u8, MazeKind::Rooms as u8],
+            seed: None,
+            maze_samples: None,
+            dead_ends: 0,
+        }
+    }
+
+    pub fn lives(mut self, lives: u8) -> Self {
+        self.lives = lives;
+        self
+    }
+    // one ghost per entry, the values are Color variants and decide the ghost's personality
+    pub fn ghost_types(mut self, types: Vec<u8>) -> Self {
+        self.ghost_types = types;
+        self
+    }
+    // the classic red, pink, cyan, orange order repeated for `count` ghosts
+    pub fn ghost_count(mut self, count: u32) -> Self {
+        self.ghost_types = Self::default_ghosts(count);
+        self
+    }
+    pub fn tunnels(mut self, tunnels: u32) -> Self {
+        self.tunnels = tunnels;
+        self
+    }
+    pub fn pacman_spawn(mut self, y: u32, x: u32) -> Self {
+        self.pacman_spawn = Some(Cord(y, x));
+        self
+    }
+    pub fn scoring(mut self, dot: u32, pellet: u32, ghost: u32, extra_life: u32) -> Self {
+        self.score_dot = dot;
+        self.score_pellet = pellet;
+        self.score_ghost = ghost;
+        self.score_extra_life = extra_life;
+        self
+    }
+    // durations of the Ready, Dying and LevelComplete phases in ticks
+    pub fn timers(mut self, ready: u32, dying: u32, level_complete: u32) -> Self {
+        self.ready_time = ready;
+        self.dying_time = dying;
+        self.level_complete_time = level_complete;
+        self
+    }
+    pub fn turn_buffer(mut self, ticks: u32) -> Self {
+        self.turn_buffer = ticks;
+        self
+    }
+    // the maze kinds the levels cycle through, e.g. `[MazeKind.Rooms]` for rooms only
+    pub fn mazes(mut self, kinds: Vec<u8>) -> Self {
+        self.mazes = kinds;
+        self
+    }
+    // boards drawn with `#` for walls, separated by empty lines, they give MazeKind.Wfc its look.
+    // Only used once Wfc is added with `mazes`
+    pub fn maze_samples(mut self, samples: String) -> Self {
+        self.maze_samples = Some(samples);
+        self
+    }
+    // 0 gives arcade style mazes without dead ends, 100 keeps all of them
+    pub fn dead_ends(mut self, percent: u32) -> Self {
+        self.dead_ends = percent.min(100);
+        self
+    }
+    // the same seed and config always give the same sequence of mazes
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+// the size a maze of width x height gets, see Level::fit_size
+pub fn checked_size(width: u32, height: u32) -> Result<(u32, u32), ConfigError> {
+    let (fitted_width, fitted_height) = Level::fit_size(width, height);
+    if fitted_width > MAX_SIZE || fitted_height > MAX_SIZE { return Err(ConfigError::InvalidSize { width, height }); }
+    Ok((fitted_width, fitted_height))
+}
+
+impl GameConfig {
+    fn default_ghosts(count: u32) -> Vec<u8> {
+        const COLORS: [Color; 4] = [Color::Red, Color::Pink, Color::Cyan, Color::Orange];
+        (0..count as usize).map(|i| COLORS[i % COLORS.len()] as u8).collect()
+    }
+
+    // checks everything that can be checked before the maze exists
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let (width, height) = checked_size(self.width, self.height)?;
+        if self.lives == 0 { return Err(ConfigError::NoLives); }
+        if self.ghost_types.len() > MAX_GHOSTS { return Err(ConfigError::TooManyGhosts(self.ghost_types.len())); }
+        if let Some(t) = self.ghost_types.iter().find(|t| Color::from_u8(**t).is_none()) {
+            return Err(ConfigError::UnknownGhostType(*t));
+        }
+        if self.mazes.is_empty() { return Err(ConfigError::NoMazes); }
+        if let Some(k) = self.mazes.iter().find(|k| MazeKind::from_u8(**k).is_none()) {
+            return Err(ConfigError::UnknownMazeKind(*k));
+        }
+        if self.tunnels as usize > level_gen::tunnel_rows(height).len() { return Err(ConfigError::TooManyTunnels(self.tunnels)); }
+        if let Some(spawn) = self.pacman_spawn.filter(|s| s.0 >= height || s.1 >= width) {
+            return Err(ConfigError::SpawnOutOfBounds(spawn));
+        }
+        Ok(())
+    }
+
+    pub fn ghost_colors(&self) -> impl Iterator<Item = Color> + '_ {
+        self.ghost_types.iter().filter_map(|t| Color::from_u8(*t))
+    }
+
+    // the generator of level `round`, counted from 1
+    pub fn maze_kind(&self, round: u32) -> MazeKind {
+        let idx = round.saturating_sub(1) as usize % self.mazes.len().max(1);
+        self.mazes.get(idx).and_then(|k| MazeKind::from_u8(*k)).unwrap_or(MazeKind::Classic)
+    }
+
+    pub fn maze(&self) -> MazeConfig {
+        MazeConfig::new(self.width, self.height, self.tunnels).dead_ends(self.dead_ends)
+    }
+
+    // the difficulty profile with the lives and ghost count of this config
+    pub fn diff(&self) -> DifficultyProfile {
+        DifficultyProfile { lives: self.lives, ghost_count: self.ghost_types.len() as u32, ..self.diff }
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+use crate::common::{Cord, Level};
+use crate::grid::Grid;
+use crate::movement::DIRS;
+use crate::nav;
+
+pub const UNREACHABLE: u32 = u32::MAX;
+
+// the flee map scales the distances by -FLEE_FACTOR/10 and re-relaxes them, so fleeing
+// ghosts prefer escape routes over running into the nearest dead end
+const FLEE_FACTOR: i64 = 12;
+const FLEE_STEP: i64 = 10;
+
+// steps from every tile to the closest of `sources`, `order` gets the reached tiles by distance
+fn bfs(level: &Level, sources: &[Cord], dist: &mut Grid<u32>, order: &mut Vec<Cord>) {
+    dist.reset(level.width(), level.height(), UNREACHABLE);
+    order.clear();
+    for source in sources.iter().filter(|source| level.in_bounds(**source)) {
+        if dist[*source] == 0 { continue; }
+        dist[*source] = 0;
+        order.push(*source);
+    }
+    let mut head = 0;
+    while let Some(&cord) = order.get(head) {
+        head += 1;
+        let d = dist[cord];
+        for dir in DIRS.iter() {
+            if let Some((next, _)) = nav::neighbor(level, cord, *dir) {
+                if dist[next] == UNREACHABLE {
+                    dist[next] = d + 1;
+                    order.push(next);
+                }
+            }
+        }
+    }
+}
+
+// BFS distances to one source tile, shared by all ghosts and only rebuilt
+// when the source moves or a wall of the level changes
+#[derive(Default)]
+pub struct DistanceField {
+    source: Option<Cord>,
+    wall_version: u64,
+    dist: Grid<u32>,
+    // reachable tiles in the order the BFS found them, so by distance
+    order: Vec<Cord>,
+    flee: Grid<i64>,
+    // the flee map is only built when a frightened ghost needs it
+    flee_fresh: bool,
+    relaxed: VecDeque<(i64, Cord)>,
+}
+
+impl DistanceField {
+    pub fn update(&mut self, level: &Level, source: Cord) {
+        let same_size = self.dist.width() == level.width() && self.dist.height() == level.height();
+        let fresh = self.source == Some(source) && self.wall_version == level.wall_version() && same_size;
+        if fresh { return; }
+        self.source = Some(source);
+        self.wall_version = level.wall_version();
+        self.calc_dist(level, source);
+        self.flee_fresh = false;
+    }
+
+    // the flee map for the last update
+    pub fn update_flee(&mut self, level: &Level) {
+        if self.flee_fresh || self.source.is_none() { return; }
+        self.calc_flee(level);
+        self.flee_fresh = true;
+    }
+
+    // forgets the cached map, needed when the level is replaced
+    pub fn invalidate(&mut self) {
+        self.source = None;
+        self.flee_fresh = false;
+    }
+
+    fn calc_dist(&mut self, level: &Level, source: Cord) {
+        bfs(level, &[source], &mut self.dist, &mut self.order);
+    }
+
+    // dijkstra without a heap: the start values ascend along the reversed BFS order and every
+    // relaxed value is the current one plus FLEE_STEP, so merging both queues keeps them in order
+    fn calc_flee(&mut self, level: &Level) {
+        self.flee.reset(level.width(), level.height(), i64::MAX);
+        self.relaxed.clear();
+        let start = |dist: &Grid<u32>, cord: Cord| -(dist[cord] as i64) * FLEE_FACTOR;
+        for cord in self.order.iter() {
+            self.flee[*cord] = start(&self.dist, *cord);
+        }
+        // the next start to take, counting down through `order`
+        let mut left = self.order.len();
+        loop {
+            let next_start = left.checked_sub(1).map(|i| (start(&self.dist, self.order[i]), self.order[i]));
+            let (value, cord) = match (next_start, self.relaxed.front().copied()) {
+                (Some(s), Some(r)) if s.0 <= r.0 => { left -= 1; s },
+                (Some(s), None) => { left -= 1; s },
+                (_, Some(r)) => { self.relaxed.pop_front(); r },
+                (None, None) => break,
+            };
+            if value > self.flee[cord] { continue; }
+            for dir in DIRS.iter() {
+                if let Some((next, _)) = nav::neighbor(level, cord, *dir) {
+                    if value + FLEE_STEP < self.flee[next] {
+                        self.flee[next] = value + FLEE_STEP;
+                        self.relaxed.push_back((value + FLEE_STEP, next));
+                    }
+                }
+            }
+        }
+    }
+
+    // steps from `cord` to the source, UNREACHABLE for walls and cut off tiles
+    pub fn dist(&self, cord: Cord) -> u32 {
+        self.dist.get(cord).copied().unwrap_or(UNREACHABLE)
+    }
+
+    // lower is safer for a tile fleeing from the source, needs update_flee
+    pub fn flee(&self, cord: Cord) -> i64 {
+        self.flee.get(cord).copied().unwrap_or(i64::MAX)
+    }
+
+    pub fn dist_map(&self) -> &Grid<u32> {
+        &self.dist
+    }
+    pub fn flee_map(&self) -> &Grid<i64> {
+        &self.flee
+    }
+
+    pub fn source(&self) -> Option<Cord> {
+        self.source
+    }
+}
+
+// steps from every tile to the closest ghost pacman has to run from
+#[derive(Default)]
+pub struct DangerMap {
+    dist: Grid<u32>,
+    order: Vec<Cord>,
+}
+
+impl DangerMap {
+    pub fn update(&mut self, level: &Level, ghosts: &[Cord]) {
+        bfs(level, ghosts, &mut self.dist, &mut self.order);
+    }
+
+    // UNREACHABLE if no ghost can get to `cord`
+    pub fn steps(&self, cord: Cord) -> u32 {
+        self.dist.get(cord).copied().unwrap_or(UNREACHABLE)
+    }
+
+    pub fn map(&self) -> &Grid<u32> {
+        &self.dist
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CORRIDOR: [&str; 5] = [
+        "#########",
+        "#.......#",
+        "#.#####.#",
+        "#.......#",
+        "#########",
+    ];
+
+    #[test]
+    fn danger_is_the_distance_to_the_closest_ghost() {
+        let level = Level::from_rows(&CORRIDOR);
+        let mut danger = DangerMap::default();
+        danger.update(&level, &[Cord(1, 1), Cord(3, 7)]);
+        assert_eq!((danger.map().width(), danger.map().height()), (9, 5));
+        assert_eq!(danger.steps(Cord(1, 1)), 0);
+        assert_eq!(danger.steps(Cord(1, 4)), 3);
+        assert_eq!(danger.steps(Cord(1, 7)), 2);
+        assert_eq!(danger.steps(Cord(2, 4)), UNREACHABLE);
+        assert_eq!(danger.steps(Cord(9, 9)), UNREACHABLE);
+
+        let mut dist = DistanceField::default();
+        dist.update(&level, Cord(1, 1));
+        danger.update(&level, &[Cord(1, 1)]);
+        assert_eq!(danger.map(), dist.dist_map());
+
+        danger.update(&level, &[]);
+        assert!(level.grid().cords().all(|cord| danger.steps(cord) == UNREACHABLE));
+    }
+}
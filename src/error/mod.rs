@@ -0,0 +1,36 @@
+use std::fmt;
+use crate::config::ConfigError;
+
+// everything that can go wrong while building or running a game, wasm_bindgen turns it into a thrown JsError
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum PacmanError {
+    Config(ConfigError),
+    // the maze generator needs odd dimensions of at least 9x7
+    InvalidFieldSize { width: u32, height: u32 },
+    InvalidShape { height: u32, left: u32, right: u32 },
+    NoDotsLeft,
+    // wfc samples need rows of the same length and at least 3x3 tiles
+    InvalidSample,
+}
+
+impl fmt::Display for PacmanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PacmanError::Config(e) => write!(f, "invalid config: {}", e),
+            PacmanError::InvalidFieldSize { width, height } =>
+                write!(f, "can't generate a {}x{} maze, the size has to be odd and at least 9x7", width, height),
+            PacmanError::InvalidShape { height, left, right } =>
+                write!(f, "invalid star shape (height {}, left {}, right {})", height, left, right),
+            PacmanError::NoDotsLeft => write!(f, "a dot was eaten but none were left"),
+            PacmanError::InvalidSample => write!(f, "maze samples need rows of the same length and at least 3x3 tiles"),
+        }
+    }
+}
+
+impl std::error::Error for PacmanError {}
+
+impl From<ConfigError> for PacmanError {
+    fn from(e: ConfigError) -> Self {
+        PacmanError::Config(e)
+    }
+}
@@ -0,0 +1,62 @@
+use wasm_bindgen::prelude::wasm_bindgen;
+
+#[wasm_bindgen]
+#[repr(u8)]
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum EventKind {
+    DotEaten = 0,
+    PelletEaten = 1,
+    GhostEaten = 2,
+    PacmanDied = 3,
+    LifeGained = 4,
+    FrightenedStarted = 5,
+    FrightenedEnding = 6,
+    FruitSpawned = 7,
+    FruitEaten = 8,
+    LevelCleared = 9,
+    GameOver = 10,
+}
+
+// everything that happened during a tick, collected until Game::drain_events
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum GameEvent {
+    DotEaten,
+    PelletEaten,
+    GhostEaten { id: u32, points: u32 },
+    PacmanDied,
+    LifeGained,
+    FrightenedStarted,
+    FrightenedEnding,
+    FruitSpawned { points: u32 },
+    FruitEaten { points: u32 },
+    LevelCleared,
+    GameOver,
+}
+
+impl GameEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            GameEvent::DotEaten => EventKind::DotEaten,
+            GameEvent::PelletEaten => EventKind::PelletEaten,
+            GameEvent::GhostEaten { .. } => EventKind::GhostEaten,
+            GameEvent::PacmanDied => EventKind::PacmanDied,
+            GameEvent::LifeGained => EventKind::LifeGained,
+            GameEvent::FrightenedStarted => EventKind::FrightenedStarted,
+            GameEvent::FrightenedEnding => EventKind::FrightenedEnding,
+            GameEvent::FruitSpawned { .. } => EventKind::FruitSpawned,
+            GameEvent::FruitEaten { .. } => EventKind::FruitEaten,
+            GameEvent::LevelCleared => EventKind::LevelCleared,
+            GameEvent::GameOver => EventKind::GameOver,
+        }
+    }
+
+    // [kind, ghost id, points] for the frontend
+    pub fn parse_for_fe(&self) -> [u32; 3] {
+        let (id, points) = match *self {
+            GameEvent::GhostEaten { id, points } => (id, points),
+            GameEvent::FruitSpawned { points } | GameEvent::FruitEaten { points } => (0, points),
+            _ => (0, 0),
+        };
+        [self.kind() as u32, id, points]
+    }
+}
@@ -0,0 +1,30 @@
+use crate::common::{Cord, TICKS_PER_SECOND};
+
+// fruit appears after 70 and 170 of the 244 arcade dots were eaten
+const SPAWN_AT: [u64; 2] = [70, 170];
+const ARCADE_DOT_COUNT: u64 = 244;
+const POINTS: [u32; 13] = [100, 300, 500, 500, 700, 700, 1000, 1000, 2000, 2000, 3000, 3000, 5000];
+const FRUIT_TIME: u32 = 19 * TICKS_PER_SECOND / 2;
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct Fruit {
+    pub cord: Cord,
+    pub points: u32,
+    ticks_left: u32,
+}
+
+impl Fruit {
+    // the fruit that appears now that `eaten` of `total` dots are gone, if any
+    pub fn spawn(cord: Cord, round: u32, eaten: u64, total: u64) -> Option<Self> {
+        let due = SPAWN_AT.iter().any(|at| (at * total / ARCADE_DOT_COUNT).max(1) == eaten);
+        if !due { return None; }
+        let points = POINTS[(round as usize - 1).min(POINTS.len() - 1)];
+        Some(Fruit { cord, points, ticks_left: FRUIT_TIME })
+    }
+
+    // returns false once the fruit is gone
+    pub fn tick(&mut self) -> bool {
+        self.ticks_left = self.ticks_left.saturating_sub(1);
+        self.ticks_left > 0
+    }
+}
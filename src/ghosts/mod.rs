@@ -1,6 +1,11 @@
-use crate::common::{BlockType, Cord, Difficulty, Entity, Color, Level};
+use crate::common::{Cord, DifficultyProfile, Entity, Color, GhostAi, Level, TICKS_PER_SECOND};
+use crate::speed::SpeedTable;
+use crate::movement::{self, Mover};
+use crate::distance::UNREACHABLE;
 use crate::common::Dir;
 use rand::{Rng, thread_rng};
+use rand::seq::SliceRandom;
+use crate::distance::DistanceField;
 
 #[derive(Clone, Copy, Eq, PartialEq)]
 pub enum GhostState {
@@ -14,75 +19,126 @@ pub enum GhostState {
 pub struct Ghost {
     pub id: u32,
     pub color: Color,
-    pub diff: Difficulty,
+    pub diff: DifficultyProfile,
     pub state: GhostState,
     pub data: Entity,
+    // the corner the ghost heads for while scattering
+    scatter_target: Cord,
+    elroy_dots: (u64, u64),
+    speeds: SpeedTable,
+    reverse_pending: bool,
 }
 
-const TIME_IN_STATE: u8 = 50;
-const VULNERABLE_TIME: u8 = 30;
+// (last round, dots left for elroy 1, dots left for elroy 2) as in the arcade, scaled to the maze size
+const ELROY_TABLE: [(u32, u64, u64); 8] = [
+    (1, 20, 10), (2, 30, 15), (5, 40, 20), (8, 50, 25),
+    (11, 60, 30), (14, 80, 40), (18, 100, 50), (u32::MAX, 120, 60),
+];
+const ARCADE_DOT_COUNT: u64 = 244;
+// ticks before the end of the frightened time in which the ghost flashes
+const FLASH_TIME: u64 = 2 * TICKS_PER_SECOND as u64;
 
 impl Ghost {
-    pub fn new(id: u32, start_cord: Cord, color: Color, diff: Difficulty) -> Self {
+    pub fn new(id: u32, start_cord: Cord, scatter_target: Cord, color: Color, diff: DifficultyProfile) -> Self {
             Self {
                 id, 
                 color, 
                 diff, 
                 state: GhostState::Scatter, 
-                data: Entity { cord: start_cord, prev_cord: start_cord, start_cord, 
-                    dir: Dir::None, prev_dir: Dir::None, tick:0 }
+                data: Entity::new(start_cord, diff.ghost_speed),
+                scatter_target,
+                elroy_dots: (0, 0),
+                speeds: SpeedTable::new(&diff, 1),
+                reverse_pending: false,
             }
     }
-    fn calc_state(&mut self) {
-        let state_c = self.data.tick % (TIME_IN_STATE as u64 * 3);
-        let expected_sate = if state_c < TIME_IN_STATE as u64 {GhostState::Scatter} else {GhostState::Chase};
+
+    // speeds and elroy thresholds depend on the round, only the red ghost turns into cruise elroy
+    pub fn set_round(&mut self, round: u32, total_dots: u64) {
+        self.speeds = SpeedTable::new(&self.diff, round);
+        if self.color != Color::Red { return; }
+        let (_, first, second) = ELROY_TABLE.iter().find(|(r, _, _)| round <= *r).unwrap_or(&ELROY_TABLE[7]);
+        let scale = |dots: u64| (dots * total_dots / ARCADE_DOT_COUNT).max(1);
+        self.elroy_dots = (scale(*first), scale(*second));
+    }
+
+    pub fn elroy_stage(&self, level: &Level) -> u8 {
+        if self.vulnerable() { return 0; }
+        let dots = level.dot_count();
+        if dots <= self.elroy_dots.1 { 2 } else if dots <= self.elroy_dots.0 { 1 } else { 0 }
+    }
+
+    fn calc_state(&mut self, level: &Level, mode: GhostState) {
+        let expected_sate = if mode == GhostState::Scatter && self.elroy_stage(level) == 0 {GhostState::Scatter} else {GhostState::Chase};
 
         if let GhostState::Vulnerable(x) = self.state {
-           if self.data.tick - x  >= VULNERABLE_TIME as u64 {self.state = expected_sate;}
+           if self.data.tick - x  >= self.diff.frightened_time as u64 {self.state = expected_sate;}
         } else {
             self.state = expected_sate;
         }
     }
 
-    fn mv(&mut self) {
-        self.data.prev_cord = self.data.cord;
-        self.data.cord = self.data.cord + self.data.dir.to_tup();
-        self.data.prev_dir = self.data.dir;
+    fn set_dir(&mut self, preferred_dirs: &[Dir], level: &Level) {
+        // the first move heads for the gate
+        if self.data.prev_dir == Dir::None {
+            let meta = level.meta();
+            self.data.dir = meta.gates.first().map_or(Dir::Up, |gate| meta.gate_exit(*gate));
+            return;
+        }
+        self.data.dir = movement::choose_dir(level, Mover::Ghost, self.data.cord, self.data.prev_dir, preferred_dirs);
     }
 
-    fn set_dir(&mut self, preferred_dirs: &mut Vec<Dir>, level: &Level) {
-        if self.data.prev_dir == Dir::None {self.data.dir = Dir::Up; return;}
-        [Dir::Down, Dir::Right, Dir::Left, Dir::Up].iter().for_each(|d| {
-            if !preferred_dirs.contains(d) {preferred_dirs.push(*d);}
-        });
+    fn next_cord(&self, dir: Dir, level: &Level) -> Option<Cord> {
+        movement::neighbor(level, self.data.cord, dir).map(|step| step.cord)
+    }
 
-        let i = preferred_dirs.iter().position(|d| *d == self.data.prev_dir.get_opposite());
-        if let Some(x) = i {
-            if self.data.prev_dir != Dir::None {preferred_dirs.remove(x);}
-        }   
+    // the ghost turns around on its next move, if the way back is free
+    pub fn reverse(&mut self) {
+        self.reverse_pending = self.data.prev_dir != Dir::None;
+    }
 
-        for dir in preferred_dirs.iter() {
-            let t = dir.to_tup();
-            let valid_move = level.block(self.data.cord + t) != BlockType::Wall && (level.block(self.data.cord + t) != BlockType::Gate || *dir == Dir::Up);
-            if valid_move {self.data.dir = *dir; return;}
+    fn set_dir_frightened(&mut self, level: &Level, dist: &DistanceField) {
+        let mut dirs = vec![Dir::Down, Dir::Right, Dir::Left, Dir::Up];
+        dirs.shuffle(&mut thread_rng());
+        if self.diff.ghost_ai == GhostAi::Pathfinding {
+            dirs.sort_by_key(|dir| self.next_cord(*dir, level).map_or(i64::MAX, |c| dist.flee(c)));
         }
+        self.set_dir(&dirs, level);
+    }
+
+    // walks down the shared distance map towards pacman
+    fn set_dir_pathfinding(&mut self, level: &Level, dist: &DistanceField) {
+        let mut dirs = vec![Dir::Down, Dir::Right, Dir::Left, Dir::Up];
+        dirs.shuffle(&mut thread_rng());
+        dirs.sort_by_key(|dir| self.next_cord(*dir, level).map_or(UNREACHABLE, |c| dist.dist(c)));
+        self.set_dir(&dirs, level);
     }
 
     fn set_dir_scatter(&mut self, level: &Level) {
-        let mut dirs = match self.id {
-            x if x%4 == 0 => vec![Dir::Up, Dir::Left],
-            x if x%4 == 1 => vec![Dir::Up, Dir::Right],
-            x if x%4 == 2 => vec![Dir::Down, Dir::Right],
-            x if x%4 == 3 => vec![Dir::Down, Dir::Left],
-            _ => vec![Dir::Up],
-        };
-        if thread_rng().gen::<bool>() {dirs.reverse();}
-        self.set_dir(&mut dirs, level);
+        self.set_dir_towards(self.scatter_target, level);
+    }
 
+    fn chase_target(&self, pacman: &Entity) -> Cord {
+        if self.diff.ghost_ai != GhostAi::Personalities { return pacman.cord; }
+        let (dy, dx) = pacman.prev_dir.to_tup();
+        let ahead = |n: i32| pacman.cord.saturating_add((dy * n, dx * n));
+        match self.color {
+            Color::Pink => ahead(4),
+            Color::Cyan => ahead(-4),
+            Color::Orange => {
+                let dist = pacman.cord.0.abs_diff(self.data.cord.0) + pacman.cord.1.abs_diff(self.data.cord.1);
+                if dist > 8 { pacman.cord } else { self.scatter_target }
+            },
+            Color::Red => pacman.cord,
+        }
+    }
+
+    fn set_dir_chase(&mut self, pacman: &Entity, level: &Level) {
+        self.set_dir_towards(self.chase_target(pacman), level);
     }
 
-    fn set_dir_chase(&mut self, pacman: Cord, level: &Level) {
-        let (off_y, off_x) = (pacman.0 as i32 - self.data.cord.0 as i32, pacman.0 as i32 - self.data.cord.0 as i32);
+    fn set_dir_towards(&mut self, target: Cord, level: &Level) {
+        let (off_y, off_x) = (target.0 as i32 - self.data.cord.0 as i32, target.1 as i32 - self.data.cord.1 as i32);
         let mut dirs : Vec<Dir> = Vec::new();
         if off_y > 0 { dirs.push(Dir::Down); } else if off_y != 0 {dirs.push(Dir::Up);}
         if off_x > 0 { dirs.push(Dir::Right); } else if off_x != 0 {dirs.push(Dir::Left);}
@@ -90,34 +146,70 @@ impl Ghost {
         if thread_rng().gen::<bool>() {dirs.reverse();}
 
         //TODO: potentially check if the current dir is not the reverse of the lst
-        self.set_dir(&mut dirs, level);
+        self.set_dir(&dirs, level);
     }
 
-    pub fn tick(&mut self, pacman: Cord, level: &Level) {
+    pub fn tick(&mut self, pacman: &Entity, level: &Level, mode: GhostState, dist: &DistanceField) {
         self.data.tick += 1;
-        self.calc_state();
-        match self.state {
-            GhostState::Vulnerable(_) | GhostState::Scatter => self.set_dir_scatter(level),
-            _ => self.set_dir_chase(pacman,level),
+        self.calc_state(level, mode);
+        let in_tunnel = movement::in_tunnel(level, self.data.cord);
+        self.data.speed = self.speeds.ghost(in_tunnel, self.vulnerable(), self.elroy_stage(level));
+        self.data.begin_tick();
+
+        let back = self.data.dir.get_opposite();
+        if std::mem::take(&mut self.reverse_pending) && (!movement::at_center(&self.data) || movement::can_move(level, Mover::Ghost, self.data.cord, back)) {
+            self.data.dir = back;
+            self.data.prev_dir = back;
+        }
+        for _ in 0..self.data.advance() {
+            //ghosts only decide where to go in the centre of a tile
+            if movement::at_center(&self.data) {
+                match self.state {
+                    GhostState::Vulnerable(_) => self.set_dir_frightened(level, dist),
+                    GhostState::Scatter => self.set_dir_scatter(level),
+                    _ if self.diff.ghost_ai == GhostAi::Pathfinding => self.set_dir_pathfinding(level, dist),
+                    _ => self.set_dir_chase(pacman,level),
+                }
+            }
+            movement::sub_step(level, &mut self.data);
         }
-        self.mv();
     }
     pub fn die(&mut self) {
-        self.data.cord = self.data.start_cord;
-        self.data.prev_dir = Dir::None;
+        self.reset();
+    }
+
+    // back into the house, as at the start of the level
+    pub fn reset(&mut self) {
+        self.data.reset();
+        self.state = GhostState::Scatter;
+        self.reverse_pending = false;
     }
 
-    // TODO: when vulnerable the ghost is slower
     pub fn set_vulnerable(&mut self) {
+       if !self.vulnerable() { self.reverse(); }
        self.state = GhostState::Vulnerable(self.data.tick);
     }
+    // only the pathfinding AI reads the shared distance maps
+    pub fn reads_distances(&self) -> bool {
+        self.diff.ghost_ai == GhostAi::Pathfinding
+    }
     pub fn vulnerable(&self) -> bool {
         if let GhostState::Vulnerable(_) = self.state {
             return true;
         }
         false
     }
-    pub fn parse_for_fe(&self) -> [u32; 8]{
-        [self.id,self.data.cord.0, self.data.cord.1,self.data.prev_cord.0, self.data.prev_cord.1,self.color as u32,self.data.prev_dir as u32, self.vulnerable() as u32]
+    // flashing warns that the frightened time is about to run out
+    pub fn flashing(&self) -> bool {
+        if let GhostState::Vulnerable(x) = self.state {
+            let frightened_time = self.diff.frightened_time as u64;
+            let remaining = frightened_time.saturating_sub(self.data.tick - x);
+            return remaining <= FLASH_TIME.min(frightened_time / 2);
+        }
+        false
+    }
+    pub fn parse_for_fe(&self) -> [i32; 11]{
+        [self.id as i32,self.data.cord.0 as i32, self.data.cord.1 as i32,self.data.prev_cord.0 as i32, self.data.prev_cord.1 as i32,self.color as i32,self.data.prev_dir as i32,
+            self.vulnerable() as i32, self.flashing() as i32, self.data.sub.0, self.data.sub.1]
     }
 }
\ No newline at end of file
@@ -0,0 +1,295 @@
+use std::ops::{Index, IndexMut};
+use crate::common::{Cord, Dir};
+use crate::movement::DIRS;
+
+// `height` rows and `width` columns of tiles from `top_left` on, without rows or columns it holds no tile
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct Rect {
+    pub top_left: Cord,
+    height: u32,
+    width: u32,
+}
+
+impl Rect {
+    pub fn new(top_left: Cord, height: u32, width: u32) -> Self {
+        Self { top_left, height, width }
+    }
+    // both corners included, empty if they are swapped
+    pub fn from_corners(top_left: Cord, bottom_right: Cord) -> Self {
+        Self::new(top_left, (bottom_right.0 + 1).saturating_sub(top_left.0), (bottom_right.1 + 1).saturating_sub(top_left.1))
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn is_empty(&self) -> bool {
+        self.height == 0 || self.width == 0
+    }
+    // the first row and column below and right of the rect
+    pub fn end(&self) -> Cord {
+        Cord(self.top_left.0 + self.height, self.top_left.1 + self.width)
+    }
+    pub fn contains(&self, cord: Cord) -> bool {
+        let end = self.end();
+        (self.top_left.0..end.0).contains(&cord.0) && (self.top_left.1..end.1).contains(&cord.1)
+    }
+    pub fn cords(&self) -> impl Iterator<Item = Cord> {
+        let (Cord(top, left), end) = (self.top_left, self.end());
+        (top..end.0).flat_map(move |y| (left..end.1).map(move |x| Cord(y, x)))
+    }
+    // the part of the rect inside of a width x height grid
+    pub fn clip(&self, width: u32, height: u32) -> Self {
+        let end = self.end();
+        Self::new(self.top_left, end.0.min(height).saturating_sub(self.top_left.0), end.1.min(width).saturating_sub(self.top_left.1))
+    }
+}
+
+// row major width x height cells, all index math of the crate goes through here
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Grid<T> {
+    width: u32,
+    height: u32,
+    cells: Vec<T>,
+}
+
+impl<T: Clone> Grid<T> {
+    pub fn new(width: u32, height: u32, value: T) -> Self {
+        Self { width, height, cells: vec![value; width as usize * height as usize] }
+    }
+
+    // changes the size, every cell is set to `value`, the allocation is reused
+    pub fn reset(&mut self, width: u32, height: u32, value: T) {
+        self.width = width;
+        self.height = height;
+        self.cells.clear();
+        self.cells.resize(width as usize * height as usize, value);
+    }
+
+    pub fn fill(&mut self, value: T) {
+        self.cells.fill(value);
+    }
+
+    // the part of the region outside of the grid is ignored
+    pub fn fill_region(&mut self, region: Rect, value: T) {
+        for cord in region.cords() {
+            if let Some(cell) = self.get_mut(cord) { *cell = value.clone(); }
+        }
+    }
+
+    // copies `src` with its top left cell at `at`, cells falling outside of the grid are dropped
+    pub fn blit(&mut self, at: Cord, src: &Grid<T>) {
+        for (cord, cell) in src.iter() {
+            let target = Cord(at.0 + cord.0, at.1 + cord.1);
+            if let Some(target) = self.get_mut(target) { *target = cell.clone(); }
+        }
+    }
+
+    // an owned copy of the region, clipped to the grid
+    pub fn sub_grid(&self, region: Rect) -> Grid<T> {
+        let view = self.view(region);
+        Grid { width: view.width(), height: view.height(), cells: view.cells().cloned().collect() }
+    }
+}
+
+impl<T> Grid<T> {
+    // None if `cells` doesn't hold exactly width * height values
+    pub fn from_vec(width: u32, height: u32, cells: Vec<T>) -> Option<Self> {
+        if cells.len() != width as usize * height as usize { return None; }
+        Some(Self { width, height, cells })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn in_bounds(&self, cord: Cord) -> bool {
+        cord.0 < self.height && cord.1 < self.width
+    }
+    pub fn to_idx(&self, cord: Cord) -> usize {
+        cord.0 as usize * self.width as usize + cord.1 as usize
+    }
+    pub fn to_cord(&self, idx: usize) -> Cord {
+        Cord((idx / self.width as usize) as u32, (idx % self.width as usize) as u32)
+    }
+
+    pub fn get(&self, cord: Cord) -> Option<&T> {
+        if !self.in_bounds(cord) { return None; }
+        self.cells.get(self.to_idx(cord))
+    }
+    pub fn get_mut(&mut self, cord: Cord) -> Option<&mut T> {
+        if !self.in_bounds(cord) { return None; }
+        let idx = self.to_idx(cord);
+        self.cells.get_mut(idx)
+    }
+
+    pub fn offset(&self, cord: Cord, off: (i32, i32)) -> Option<Cord> {
+        cord.checked_add(off).filter(|next| self.in_bounds(*next))
+    }
+    // the cell next to `cord` in `dir`, None at the edge of the grid
+    pub fn neighbor(&self, cord: Cord, dir: Dir) -> Option<Cord> {
+        if dir == Dir::None { return None; }
+        self.offset(cord, dir.to_tup())
+    }
+    pub fn neighbors(&self, cord: Cord) -> impl Iterator<Item = (Dir, Cord)> + '_ {
+        DIRS.iter().filter_map(move |dir| self.neighbor(cord, *dir).map(|next| (*dir, next)))
+    }
+
+    pub fn cords(&self) -> impl Iterator<Item = Cord> {
+        let width = self.width;
+        (0..self.height).flat_map(move |y| (0..width).map(move |x| Cord(y, x)))
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (Cord, &T)> {
+        let width = self.width as usize;
+        self.cells.iter().enumerate().map(move |(idx, cell)| (Cord((idx / width) as u32, (idx % width) as u32), cell))
+    }
+
+    pub fn row(&self, y: u32) -> &[T] {
+        let start = y as usize * self.width as usize;
+        &self.cells[start..start + self.width as usize]
+    }
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> {
+        self.cells.chunks(self.width.max(1) as usize)
+    }
+    pub fn col(&self, x: u32) -> impl Iterator<Item = &T> {
+        let cells = if x < self.width { &self.cells[x as usize..] } else { &[] };
+        cells.iter().step_by(self.width.max(1) as usize)
+    }
+
+    // a read only window into the region, clipped to the grid
+    pub fn view(&self, region: Rect) -> GridView<'_, T> {
+        GridView { grid: self, region: region.clip(self.width, self.height) }
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.cells
+    }
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.cells
+    }
+    pub fn as_ptr(&self) -> *const T {
+        self.cells.as_ptr()
+    }
+}
+
+impl<T> Default for Grid<T> {
+    fn default() -> Self {
+        Self { width: 0, height: 0, cells: Vec::new() }
+    }
+}
+
+impl<T> Index<Cord> for Grid<T> {
+    type Output = T;
+    fn index(&self, cord: Cord) -> &T {
+        &self.cells[self.to_idx(cord)]
+    }
+}
+
+impl<T> IndexMut<Cord> for Grid<T> {
+    fn index_mut(&mut self, cord: Cord) -> &mut T {
+        let idx = self.to_idx(cord);
+        &mut self.cells[idx]
+    }
+}
+
+// a read only window into a grid, cords are relative to the top left of the region
+pub struct GridView<'a, T> {
+    grid: &'a Grid<T>,
+    region: Rect,
+}
+
+impl<'a, T> GridView<'a, T> {
+    pub fn width(&self) -> u32 {
+        self.region.width()
+    }
+    pub fn height(&self) -> u32 {
+        self.region.height()
+    }
+    pub fn region(&self) -> Rect {
+        self.region
+    }
+    pub fn get(&self, cord: Cord) -> Option<&'a T> {
+        if cord.0 >= self.height() || cord.1 >= self.width() { return None; }
+        self.grid.get(Cord(self.region.top_left.0 + cord.0, self.region.top_left.1 + cord.1))
+    }
+    pub fn cells(&self) -> impl Iterator<Item = &'a T> {
+        let grid = self.grid;
+        self.region.cords().map(move |cord| &grid[cord])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_rects_hold_no_tile() {
+        for top_left in [Cord(3, 4), Cord(0, 0), Cord(0, 4), Cord(3, 0)].iter().copied() {
+            for (height, width) in [(0, 0), (0, 3), (2, 0)].iter().copied() {
+                let rect = Rect::new(top_left, height, width);
+                assert!(rect.is_empty());
+                assert_eq!(rect.cords().count(), 0);
+                assert!(!rect.contains(top_left));
+            }
+        }
+        assert_eq!(Rect::new(Cord(3, 4), 2, 5).cords().count(), 10);
+        assert_eq!(Rect::from_corners(Cord(1, 1), Cord(2, 3)), Rect::new(Cord(1, 1), 2, 3));
+        assert!(Rect::from_corners(Cord(2, 1), Cord(1, 3)).is_empty());
+    }
+
+    #[test]
+    fn fill_region_clips_at_the_edges() {
+        let mut grid = Grid::new(4, 3, 0);
+        grid.fill_region(Rect::new(Cord(1, 2), 5, 5), 1);
+        assert_eq!(grid.as_slice(), &[0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 1, 1]);
+        assert!(Grid::from_vec(4, 3, vec![0; 11]).is_none());
+    }
+
+    fn numbered(width: u32, height: u32) -> Grid<u32> {
+        Grid::from_vec(width, height, (0..width * height).collect()).unwrap()
+    }
+
+    #[test]
+    fn rows_and_cols() {
+        let grid = numbered(4, 3);
+        assert_eq!(grid.row(1), &[4, 5, 6, 7]);
+        assert_eq!(grid.rows().count(), 3);
+        assert_eq!(grid.rows().last(), Some(&[8, 9, 10, 11][..]));
+        assert_eq!(grid.col(2).copied().collect::<Vec<_>>(), vec![2, 6, 10]);
+        assert_eq!(grid.col(4).count(), 0);
+        assert_eq!(Grid::<u32>::default().rows().count(), 0);
+    }
+
+    #[test]
+    fn views_are_clipped_and_relative() {
+        let grid = numbered(4, 3);
+        let view = grid.view(Rect::new(Cord(1, 2), 5, 5));
+        assert_eq!((view.height(), view.width()), (2, 2));
+        assert_eq!(view.region(), Rect::new(Cord(1, 2), 2, 2));
+        assert_eq!(view.get(Cord(0, 0)), Some(&6));
+        assert_eq!(view.get(Cord(1, 1)), Some(&11));
+        assert_eq!(view.get(Cord(0, 2)), None);
+        assert_eq!(view.cells().copied().collect::<Vec<_>>(), vec![6, 7, 10, 11]);
+        assert_eq!(grid.view(Rect::new(Cord(5, 0), 1, 1)).cells().count(), 0);
+        assert_eq!(grid.view(Rect::new(Cord(0, 0), 0, 4)).cells().count(), 0);
+    }
+
+    #[test]
+    fn sub_grids_blit_back_to_the_same_place() {
+        let grid = numbered(4, 3);
+        let part = grid.sub_grid(Rect::new(Cord(1, 1), 2, 2));
+        assert_eq!((part.width(), part.height()), (2, 2));
+        assert_eq!(part.as_slice(), &[5, 6, 9, 10]);
+
+        let mut copy = Grid::new(4, 3, 0);
+        copy.blit(Cord(1, 1), &part);
+        assert_eq!(copy.as_slice(), &[0, 0, 0, 0, 0, 5, 6, 0, 0, 9, 10, 0]);
+        // cells past the edge are dropped
+        copy.blit(Cord(2, 3), &part);
+        assert_eq!(copy.row(2), &[0, 9, 10, 5]);
+    }
+}
@@ -0,0 +1,99 @@
+use crate::common::{Dir, Cord, BlockType};
+use crate::error::PacmanError;
+use crate::grid::Grid;
+use crate::movement::DIRS;
+use rand::RngCore;
+use rand::seq::SliceRandom;
+use super::{build, Field, GenStep, Layout, LevelMeta, MazeConfig, MazeGenerator, Progress};
+
+// moves of the backtracker and cells checked for dead ends in one step of the layout
+const CELLS_PER_STEP: usize = 2048;
+
+// a depth first maze on the odd tiles, the walls between two cells are single tiles so the
+// corridors stay one tile wide. Dead ends are braided away afterwards, pacman can always escape
+pub struct Backtracker;
+
+impl Field {
+    // odd tiles inside the border, the crossings of the maze
+    fn is_cell(&self, cord: Cord) -> bool {
+        cord.0 % 2 == 1 && cord.1 % 2 == 1 && cord.0 < self.field.height() - 1 && cord.1 < self.field.width() - 1
+    }
+
+    // the cell two tiles away in `dir` and the wall in between
+    fn cell_step(&self, cord: Cord, dir: Dir) -> Option<(Cord, Cord)> {
+        let wall = self.field.neighbor(cord, dir)?;
+        let next = self.field.neighbor(wall, dir)?;
+        Some((wall, next)).filter(|_| self.is_cell(next))
+    }
+
+    fn open_sides(&self, cord: Cord) -> usize {
+        DIRS.iter().filter(|dir| !self.is_wall(self.field.neighbor(cord, **dir))).count()
+    }
+
+    // up to CELLS_PER_STEP moves of the backtracker along `stack`
+    fn carve_backtracker(&mut self, rng: &mut dyn RngCore, stack: &mut Vec<Cord>) {
+        for _ in 0..CELLS_PER_STEP {
+            let Some(&cord) = stack.last() else { return; };
+            let mut dirs = DIRS;
+            dirs.shuffle(rng);
+            let step = dirs.iter()
+                .filter_map(|dir| self.cell_step(cord, *dir))
+                .find(|(_, next)| self.field[*next] == BlockType::Wall);
+            match step {
+                Some((wall, next)) => {
+                    self.set(wall, BlockType::PacDot);
+                    self.set(next, BlockType::PacDot);
+                    stack.push(next);
+                }
+                None => { stack.pop(); }
+            }
+        }
+    }
+
+    // opens one more wall of every dead end out of the last CELLS_PER_STEP `cells`, joining two dead ends removes both at once
+    fn braid_cells(&mut self, rng: &mut dyn RngCore, cells: &mut Vec<Cord>) {
+        for cord in cells.drain(cells.len().saturating_sub(CELLS_PER_STEP)..).rev() {
+            if self.open_sides(cord) != 1 { continue; }
+            let mut options: Vec<(Cord, Cord)> = DIRS.iter()
+                .filter_map(|dir| self.cell_step(cord, *dir))
+                .filter(|(wall, _)| self.field[*wall] == BlockType::Wall)
+                .collect();
+            options.shuffle(rng);
+            options.sort_by_key(|(_, next)| self.open_sides(*next) != 1);
+            if let Some((wall, _)) = options.first() { self.set(*wall, BlockType::PacDot); }
+        }
+    }
+}
+
+impl Layout for Backtracker {
+    fn layout_step(&self, rng: &mut dyn RngCore, field: &mut Field, progress: &mut Progress) -> Result<GenStep, PacmanError> {
+        match progress {
+            Progress::Start => {
+                let start = Cord(1, 1);
+                field.fill_walls();
+                field.set(start, BlockType::PacDot);
+                *progress = Progress::Carving(vec![start]);
+            },
+            Progress::Carving(stack) => {
+                field.carve_backtracker(rng, stack);
+                if stack.is_empty() {
+                    let mut cells: Vec<Cord> = field.field.cords().filter(|cord| field.is_cell(*cord)).collect();
+                    cells.reverse();
+                    *progress = Progress::Braiding(cells);
+                }
+            },
+            Progress::Braiding(cells) => {
+                field.braid_cells(rng, cells);
+                if cells.is_empty() { *progress = Progress::Done; }
+            },
+            _ => return Ok(GenStep::Done),
+        }
+        Ok(GenStep::Layout)
+    }
+}
+
+impl MazeGenerator for Backtracker {
+    fn generate(&self, rng: &mut dyn RngCore, config: &MazeConfig) -> Result<(Grid<BlockType>, LevelMeta), PacmanError> {
+        build(self, rng, config)
+    }
+}
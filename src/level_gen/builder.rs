@@ -0,0 +1,124 @@
+use wasm_bindgen::prelude::{wasm_bindgen, JsError};
+use crate::common::{Cord, BlockType, Level};
+use crate::config::checked_size;
+use crate::error::PacmanError;
+use rand::{SeedableRng, rngs::StdRng};
+use super::{Field, Layout, MazeConfig, MazeKind, Progress, Wfc, FINISH_STEPS};
+
+// what the last LevelBuilder::step did
+#[wasm_bindgen]
+#[repr(u8)]
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum GenStep {
+    // one wall rectangle of the classic generator
+    Rect = 0,
+    // one wall cross of the classic generator
+    Star = 1,
+    // a part of the layout of the other generators
+    Layout = 2,
+    Spawn = 3,
+    PostProcessing = 4,
+    Pellets = 5,
+    Braid = 6,
+    Tunnels = 7,
+    // nothing left to do, the level can be built
+    Done = 8,
+}
+
+enum Stage {
+    Layout(Progress),
+    // index into FINISH_STEPS
+    Finish(usize),
+    Done,
+}
+
+// builds a maze a little at a time, so the frontend can show it being built or spread a
+// huge maze over several frames. Steps the same way as Level::generate with the same rng
+#[wasm_bindgen]
+pub struct LevelBuilder {
+    field: Field,
+    config: MazeConfig,
+    kind: MazeKind,
+    // learned from custom samples, None uses the arcade maze for MazeKind::Wfc
+    wfc: Option<Wfc>,
+    rng: StdRng,
+    stage: Stage,
+}
+
+#[wasm_bindgen]
+impl LevelBuilder {
+    #[wasm_bindgen(js_name = new)]
+    pub fn js_new(kind: MazeKind, width: u32, height: u32, tunnels: u32, seed: u64) -> Result<LevelBuilder, JsError> {
+        Ok(Self::new(kind, &MazeConfig::new(width, height, tunnels), StdRng::seed_from_u64(seed))?)
+    }
+
+    #[wasm_bindgen(js_name = step)]
+    pub fn js_step(&mut self) -> Result<GenStep, JsError> {
+        Ok(self.step()?)
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(self.stage, Stage::Done)
+    }
+    pub fn kind(&self) -> MazeKind {
+        self.kind
+    }
+    pub fn field(&self) -> *const BlockType {
+        self.field.field.as_ptr()
+    }
+    pub fn width(&self) -> u32 {
+        self.field.field.width()
+    }
+    pub fn height(&self) -> u32 {
+        self.field.field.height()
+    }
+    // Wall outside of the field
+    pub fn field_at(&self, y: u32, x: u32) -> BlockType {
+        self.field.field.get(Cord(y, x)).copied().unwrap_or(BlockType::Wall)
+    }
+}
+
+impl LevelBuilder {
+    // the size of the config is fitted like in Level::generate and checked like in GameConfig::validate
+    pub fn new(kind: MazeKind, config: &MazeConfig, rng: StdRng) -> Result<Self, PacmanError> {
+        let (width, height) = checked_size(config.width, config.height)?;
+        let config = MazeConfig { width, height, ..*config };
+        let field = Field::new(width, height)?;
+        Ok(Self { field, config, kind, wfc: None, rng, stage: Stage::Layout(Progress::Start) })
+    }
+
+    // MazeKind::Wfc learns from these samples instead of the arcade maze
+    pub fn with_wfc(mut self, wfc: Wfc) -> Self {
+        self.wfc = Some(wfc);
+        self
+    }
+
+    pub fn step(&mut self) -> Result<GenStep, PacmanError> {
+        if let Stage::Layout(progress) = &mut self.stage {
+            let layout: &dyn Layout = match (self.kind, &self.wfc) {
+                (MazeKind::Wfc, Some(wfc)) => wfc,
+                (kind, _) => kind.layout(),
+            };
+            let step = layout.layout_step(&mut self.rng, &mut self.field, progress)?;
+            if step != GenStep::Done { return Ok(step); }
+            // the layout is complete, the call goes on with the first step of finish
+            self.stage = Stage::Finish(0);
+        }
+        match self.stage {
+            Stage::Finish(i) => {
+                let step = FINISH_STEPS[i];
+                self.field.finish_step(&mut self.rng, &self.config, step);
+                self.stage = if i + 1 < FINISH_STEPS.len() { Stage::Finish(i + 1) } else { Stage::Done };
+                Ok(step)
+            },
+            Stage::Layout(_) | Stage::Done => Ok(GenStep::Done),
+        }
+    }
+
+    // runs the steps that are left
+    pub fn build(mut self) -> Result<Level, PacmanError> {
+        while self.step()? != GenStep::Done {}
+        let meta = self.field.meta();
+        Ok(Level::from_maze(self.field.field, meta))
+    }
+}
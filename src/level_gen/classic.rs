@@ -0,0 +1,200 @@
+use crate::common::{Dir, Cord, BlockType};
+use crate::error::PacmanError;
+use crate::grid::Grid;
+use rand::{Rng, RngCore};
+use super::{build, Field, GenStep, Layout, LevelMeta, MazeConfig, MazeGenerator, Progress};
+
+trait BlockPositions {
+    fn block_positions(&self) -> Result<Vec<(i32, i32)>, PacmanError>;
+}
+
+struct Rectangle {
+    width: u32,
+    height: u32,
+}
+
+//the origin is included in all sizes
+struct Star {
+    height: u32,
+    offset: u32,
+    left: u32,
+    right: u32,
+}
+
+impl BlockPositions for Rectangle {
+    fn block_positions(&self) -> Result<Vec<(i32, i32)>, PacmanError> {
+        let mut positions: Vec<(i32, i32)> = Vec::with_capacity(self.width as usize * self.height as usize);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                positions.push((y as i32, x as i32));
+            }
+        }
+        Ok(positions)
+    }
+}
+
+impl BlockPositions for Star {
+    fn block_positions(&self) -> Result<Vec<(i32, i32)>, PacmanError> {
+        let mut positions = Vec::with_capacity((self.height + self.left + self.right) as usize);
+
+        if self.height < 1 || self.right < 1 || self.left < 1 {
+            return Err(PacmanError::InvalidShape { height: self.height, left: self.left, right: self.right });
+        }
+
+        for y in 0..self.height {
+            positions.push((y as i32, 0));
+        }
+        for x in -(self.left as i32 - 1)..(self.right as i32) {
+            positions.push((self.offset as i32, x));
+        }
+
+        Ok(positions)
+    }
+}
+
+const MAX_WALL_LENGTH: u32 = 7;
+
+impl Field {
+    //max length in one direction -> pos is included
+    fn get_max_dir(&self, cord: Cord, dir: &Dir, for_star: bool) -> u32 {
+        self.get_max_dir_up_to(cord, dir, for_star, u32::MAX)
+    }
+
+    // get_max_dir capped at `limit`, only walks the first limit + 1 tiles of the ray
+    fn get_max_dir_up_to(&self, cord: Cord, dir: &Dir, for_star: bool, limit: u32) -> u32 {
+        let valid_distance = |block: &Cord| {
+            let perpendicular = dir.get_perpendicular_dirs();
+            let distances = (self.get_distance_to_wall(*block, &perpendicular[0]), self.get_distance_to_wall(*block, &perpendicular[1]));
+            (distances.0 != 0 && distances.1 != 0) && (!for_star || distances.0 != 2 && distances.1 != 2)
+        };
+        let n = self.ray(cord, *dir)
+            .take(limit.saturating_add(1) as usize)
+            .position(|block| !valid_distance(&block) || self.field[block] == BlockType::Wall);
+        match n {
+            Some(n) => (n as u32).saturating_sub(1),
+            // the border is a wall, so a ray without a stop was cut by the limit
+            None => limit,
+        }
+    }
+
+    fn get_rand_size(&self, rng: &mut dyn RngCore, cord: Cord, direction: &Dir, for_star: bool) -> u32 {
+        const PREFFERED_LEN: u32 = 3;
+        let max = self.get_max_dir_up_to(cord, direction, for_star, MAX_WALL_LENGTH);
+        if max == 0 { return 0; }
+        let mut rand: u32;
+        if max > PREFFERED_LEN { rand = rng.gen_range(PREFFERED_LEN..=max); } else { rand = max; };
+        let off = (direction.to_tup().0 * (rand - 1) as i32, direction.to_tup().1 * (rand - 1) as i32);
+
+        if self.field.offset(cord, off).is_some_and(|end| self.get_distance_to_wall(end, direction) == 2) {
+            rand += 1;
+        }
+        rand
+    }
+    fn insert_random_rect(&mut self, rng: &mut dyn RngCore, cord: Cord) -> Result<u32, PacmanError> {
+        let mut height = self.get_rand_size(rng, cord, &Dir::Down, false);
+        loop {
+            let (mut max_width, mut off_y) = (u32::MAX, 0);
+            for y in 0..height {
+                // rows at least as wide as the narrowest so far are not looked at further
+                let curr_max_width = self.get_max_dir_up_to(Cord(cord.0 + y, cord.1), &Dir::Right, false, max_width);
+                if curr_max_width < max_width {
+                    max_width = curr_max_width;
+                    off_y = y;
+                }
+            }
+            if max_width == 0 {
+                height -= 1;
+                continue;
+            }
+            let width = self.get_rand_size(rng, Cord(cord.0 + off_y, cord.1), &Dir::Right, false);
+            self.insert_shape(cord, &Rectangle { width, height }.block_positions()?);
+            break Ok(width);
+        }
+    }
+
+    fn insert_random_star(&mut self, rng: &mut dyn RngCore, cord: Cord) -> Result<u32, PacmanError> {
+        let height = self.get_rand_size(rng, cord, &Dir::Down, false);
+
+        let mut distances_per_block: Vec<u32> = Vec::new();
+        for diff_y in 0..height {
+            let max_right = self.get_max_dir(Cord(cord.0 + diff_y, cord.1), &Dir::Right, true);
+            let max_left = self.get_max_dir(Cord(cord.0 + diff_y, cord.1), &Dir::Left, true);
+            if (max_right != 1 || max_left != 1) && (max_right != 0 && max_left != 0) {
+                distances_per_block.extend(vec![diff_y; (max_left + max_right) as usize - 1]);
+            }
+        }
+
+        let mut star = Star { height, offset: 0, left: 1, right: 1 };
+        if !distances_per_block.is_empty() {
+            let idx = rng.gen_range(0..distances_per_block.len());
+            let offset = distances_per_block[idx];
+
+            let right = self.get_rand_size(rng, Cord(cord.0 + offset, cord.1), &Dir::Right, true);
+            let left = self.get_rand_size(rng, Cord(cord.0 + offset, cord.1), &Dir::Left, true);
+
+            star = Star { height, offset, right, left };
+        }
+        self.insert_shape(cord, &star.block_positions()?);
+
+        Ok(1)
+    }
+
+    // scans on from `cursor` and places the next rectangle or star, None once the field is full
+    fn place_next(&mut self, rng: &mut dyn RngCore, cursor: &mut Cord) -> Result<Option<GenStep>, PacmanError> {
+        while cursor.0 < self.field.height() - 2 {
+            let cord = *cursor;
+            if cord.1 >= self.field.width() - 2 {
+                *cursor = Cord(cord.0 + 1, 2);
+                continue;
+            }
+            if self.field[cord] == BlockType::Wall || self.neighbours(cord) > 0 {
+                cursor.1 += 1;
+                continue;
+            }
+
+            // only whether there is room matters here
+            let fits = |dir: &Dir, for_star: bool| self.get_max_dir_up_to(cord, dir, for_star, 1) > 0;
+            let star_valid = fits(&Dir::Right, true) && fits(&Dir::Down, true);
+            let rect_valid = fits(&Dir::Down, false) && fits(&Dir::Right, false);
+
+            let rand = rng.gen::<u8>();
+            let is_first_element = cord == Cord(2, 2);
+
+            let step = if (is_first_element || rand < (u8::MAX / 3) || !star_valid) && rect_valid {
+                cursor.1 += self.insert_random_rect(rng, cord)?;
+                Some(GenStep::Rect)
+            } else if star_valid {
+                cursor.1 += self.insert_random_star(rng, cord)?;
+                Some(GenStep::Star)
+            } else {
+                None
+            };
+            cursor.1 += 1;
+            if step.is_some() { return Ok(step); }
+        }
+        Ok(None)
+    }
+}
+
+// rectangles and crosses of wall placed row by row into an open field, the original generator
+pub struct Classic;
+
+impl Layout for Classic {
+    // one rectangle or star per step
+    fn layout_step(&self, rng: &mut dyn RngCore, field: &mut Field, progress: &mut Progress) -> Result<GenStep, PacmanError> {
+        if let Progress::Start = progress {
+            field.init();
+            *progress = Progress::Passages(Cord(2, 2));
+        }
+        let Progress::Passages(cursor) = progress else { return Ok(GenStep::Done); };
+        let step = field.place_next(rng, cursor)?;
+        if step.is_none() { *progress = Progress::Done; }
+        Ok(step.unwrap_or(GenStep::Done))
+    }
+}
+
+impl MazeGenerator for Classic {
+    fn generate(&self, rng: &mut dyn RngCore, config: &MazeConfig) -> Result<(Grid<BlockType>, LevelMeta), PacmanError> {
+        build(self, rng, config)
+    }
+}
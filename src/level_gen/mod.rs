@@ -1,85 +1,260 @@
+use wasm_bindgen::prelude::wasm_bindgen;
 use crate::common::{Dir,Cord, BlockType};
-use rand::{Rng, thread_rng};
-use std::cmp;
+use crate::error::PacmanError;
+use crate::movement::DIRS;
+use crate::grid::{Grid, Rect};
+use rand::RngCore;
+use rand::seq::SliceRandom;
+use std::collections::VecDeque;
+
+mod classic;
+mod backtracker;
+mod rooms;
+mod wfc;
+mod builder;
+
+pub use classic::Classic;
+pub use backtracker::Backtracker;
+pub use rooms::Rooms;
+pub use wfc::{Wfc, DEFAULT_SAMPLE};
+use wfc::Collapse;
+pub use builder::{GenStep, LevelBuilder};
+
+// the most walls braiding carves through to open a dead end
+const MAX_BRAID_LENGTH: usize = 4;
+// open tiles closing a 2x2 area looks at around a tile before it searches the whole maze
+const CUT_SEARCH: usize = 1024;
+
+// sizes have to be odd and at least 9x7, Level::fit_size takes care of that
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct MazeConfig {
+    pub width: u32,
+    pub height: u32,
+    pub tunnels: u32,
+    // percent of the dead ends that may stay, 0 braids all of them away
+    pub dead_ends: u32,
+}
+
+impl MazeConfig {
+    pub fn new(width: u32, height: u32, tunnels: u32) -> Self {
+        Self { width, height, tunnels, dead_ends: 0 }
+    }
 
-trait BlockPositions {
-    fn block_positions(&self) -> Vec<(i32, i32)>;
+    pub fn dead_ends(mut self, percent: u32) -> Self {
+        self.dead_ends = percent.min(100);
+        self
+    }
 }
 
-struct Rectangle {
-    width: u32,
-    height: u32,
+// a maze layout algorithm, the same rng state and config always give the same maze.
+// Every generator ends with the ghost house, pellets and tunnels of Field::finish
+pub trait MazeGenerator {
+    fn generate(&self, rng: &mut dyn RngCore, config: &MazeConfig) -> Result<(Grid<BlockType>, LevelMeta), PacmanError>;
 }
 
-//the origin is included in all sizes
-struct Star {
-    height: u32,
-    offset: u32,
-    left: u32,
-    right: u32,
+// what a generator does before Field::finish, LevelBuilder shows it before the rules are applied.
+// It is done in steps of bounded work, so a huge maze can be spread over several frames
+trait Layout {
+    // the next step of the layout, GenStep::Done once it is complete
+    fn layout_step(&self, rng: &mut dyn RngCore, field: &mut Field, progress: &mut Progress) -> Result<GenStep, PacmanError>;
+
+    fn layout(&self, rng: &mut dyn RngCore, field: &mut Field) -> Result<(), PacmanError> {
+        let mut progress = Progress::Start;
+        while self.layout_step(rng, field, &mut progress)? != GenStep::Done {}
+        Ok(())
+    }
 }
 
-struct Field {
-    width: u32,
-    height: u32,
-    field: Vec<BlockType>,
+// how far a layout got, every layout keeps its own kind of progress
+enum Progress {
+    Start,
+    // the classic generator scans the field for the next shape from here
+    Passages(Cord),
+    // the path of the backtracker, the last cell is the one it carves from
+    Carving(Vec<Cord>),
+    // cells of the backtracker still to check for dead ends, the next one last
+    Braiding(Vec<Cord>),
+    // the rooms placed so far and the tries left to place more
+    Placing(Vec<Rect>, u32),
+    // the rooms to carve and the next one of them
+    Rooms(Vec<Rect>, usize),
+    Collapsing(Collapse),
+    // the learned layout is in place, areas it cut off still have to be joined
+    Connecting,
+    Done,
 }
 
-impl BlockPositions for Rectangle {
-    fn block_positions(&self) -> Vec<(i32, i32)> {
-        let mut positions: Vec<(i32, i32)> = Vec::with_capacity((self.width * self.height) as usize);
-        for y in 0..self.height {
-            for x in 0..self.width {
-                positions.push((y as i32, x as i32));
-            }
-        }
-        positions
-    }
+fn build(layout: &dyn Layout, rng: &mut dyn RngCore, config: &MazeConfig) -> Result<(Grid<BlockType>, LevelMeta), PacmanError> {
+    let mut field = Field::new(config.width, config.height)?;
+    layout.layout(rng, &mut field)?;
+    Ok(field.finish(rng, config))
 }
 
-impl BlockPositions for Star {
-    fn block_positions(&self) -> Vec<(i32, i32)> {
-        let mut positions = Vec::with_capacity((self.height + self.left + self.right) as usize);
+// the steps of Field::finish in order
+const FINISH_STEPS: [GenStep; 5] = [GenStep::Spawn, GenStep::PostProcessing, GenStep::Pellets, GenStep::Braid, GenStep::Tunnels];
+
+#[wasm_bindgen]
+#[repr(u8)]
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum MazeKind {
+    // walls as rectangles and crosses
+    Classic = 0,
+    // a recursive backtracker maze without dead ends
+    Braided = 1,
+    // pillared rooms joined by corridors
+    Rooms = 2,
+    // learned from a sample maze, the arcade one unless GameConfig::maze_samples gives others
+    Wfc = 3,
+}
 
-        if self.height < 1 || self.right < 1 || self.left < 1 {
-            panic!("Dimensions invalid! (block_positions(...))")
+impl MazeKind {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(MazeKind::Classic),
+            1 => Some(MazeKind::Braided),
+            2 => Some(MazeKind::Rooms),
+            3 => Some(MazeKind::Wfc),
+            _ => None,
         }
+    }
 
-        for y in 0..self.height {
-            positions.push((y as i32, 0));
-        }
-        for x in (self.left as i32 - 1) * -1..(self.right as i32) {
-            positions.push((self.offset as i32, x));
+    pub fn generator(self) -> &'static dyn MazeGenerator {
+        match self {
+            MazeKind::Classic => &Classic,
+            MazeKind::Braided => &Backtracker,
+            MazeKind::Rooms => &Rooms,
+            MazeKind::Wfc => Wfc::arcade(),
         }
+    }
 
-        positions
+    fn layout(self) -> &'static dyn Layout {
+        match self {
+            MazeKind::Classic => &Classic,
+            MazeKind::Braided => &Backtracker,
+            MazeKind::Rooms => &Rooms,
+            MazeKind::Wfc => Wfc::arcade(),
+        }
     }
 }
 
-const MAX_WALL_LENGTH: u32 = 7;
+struct Field {
+    field: Grid<BlockType>,
+    // open tiles between a tile and the next wall, one grid per Dir (the tile itself doesn't count).
+    // Kept up to date by `set` and `insert_shape`, so shape placement never has to walk the field
+    to_wall: [Grid<u32>; 4],
+}
+
+// where things are in a generated maze, so nobody has to guess from the maze size
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct LevelMeta {
+    // reachable from the rest of the maze, outside of the ghost house
+    pub pacman_spawn: Cord,
+    // the house tile right below the gate
+    pub ghost_spawn: Cord,
+    // the house including its walls and the gate
+    pub ghost_house: Rect,
+    pub house_tiles: Vec<Cord>,
+    pub gates: Vec<Cord>,
+    pub pellets: Vec<Cord>,
+    pub fruit: Cord,
+    // top left, top right, bottom right, bottom left
+    pub scatter_corners: [Cord; 4],
+}
+
+impl LevelMeta {
+    // the way out of the house through `gate`, given by the side of the house it sits on
+    pub fn gate_exit(&self, gate: Cord) -> Dir {
+        let house = self.ghost_house;
+        if gate.0 == house.top_left.0 { Dir::Up }
+        else if gate.0 + 1 == house.end().0 { Dir::Down }
+        else if gate.1 == house.top_left.1 { Dir::Left }
+        else { Dir::Right }
+    }
+}
 
 impl Field {
-    pub fn new(width: u32, height: u32) -> Self {
+    pub fn new(width: u32, height: u32) -> Result<Self, PacmanError> {
         if width % 2 == 0 || height % 2 == 0 || width < 9 || height < 7 {
-            panic!("Invalid width or height! (new(...))")
+            return Err(PacmanError::InvalidFieldSize { width, height });
         }
-        Self {
-            width,
-            height,
-            field: Vec::with_capacity((width * height) as usize),
+        let to_wall = [(); 4].map(|_| Grid::new(width, height, 0));
+        Ok(Self { field: Grid::new(width, height, BlockType::PacDot), to_wall })
+    }
+
+    fn set(&mut self, cord: Cord, block: BlockType) {
+        let was_wall = self.field[cord] == BlockType::Wall;
+        self.field[cord] = block;
+        if was_wall != (block == BlockType::Wall) {
+            DIRS.iter().for_each(|dir| self.update_to_wall(cord, *dir));
+        }
+    }
+
+    // like `set` for a large part of the field, the distances are rebuilt once at the end
+    fn set_many(&mut self, cords: impl IntoIterator<Item = Cord>, block: BlockType) {
+        cords.into_iter().for_each(|cord| self.field[cord] = block);
+        self.rebuild_to_wall();
+    }
+
+    // only the tiles looking at `cord` in `dir` change, up to and including the first wall behind it
+    fn update_to_wall(&mut self, cord: Cord, dir: Dir) {
+        let (h, w) = (self.field.height() as usize, self.field.width() as usize);
+        let (dy, dx) = dir.to_tup();
+        let behind = match (dy, dx) {
+            (1, _) => cord.0 as usize,
+            (-1, _) => h - 1 - cord.0 as usize,
+            (_, 1) => cord.1 as usize,
+            _ => w - 1 - cord.1 as usize,
+        };
+        let back = -(dy as isize * w as isize + dx as isize);
+        let walls = self.field.as_slice();
+        let to_wall = self.to_wall[dir as usize].as_mut_slice();
+        let mut curr = cord.0 as usize * w + cord.1 as usize;
+        let first = if walls[curr] == BlockType::Wall { 0 } else { to_wall[curr] + 1 };
+        // every tile further back is one more step away, once a value is unchanged the rest is as well
+        for dist in first..first + behind as u32 {
+            curr = (curr as isize + back) as usize;
+            let old = std::mem::replace(&mut to_wall[curr], dist);
+            if old == dist || walls[curr] == BlockType::Wall { break; }
+        }
+    }
+
+    fn rebuild_to_wall(&mut self) {
+        let (h, w) = (self.field.height() as usize, self.field.width() as usize);
+        let walls: Vec<bool> = self.field.as_slice().iter().map(|block| *block == BlockType::Wall).collect();
+        for dir in DIRS {
+            let (dy, dx) = dir.to_tup();
+            let to_wall = self.to_wall[dir as usize].as_mut_slice();
+            // against `dir`, the neighbor in `dir` has to be known before the tile itself
+            for row in 0..h {
+                let y = if dy > 0 { h - 1 - row } else { row };
+                for col in 0..w {
+                    let x = if dx > 0 { w - 1 - col } else { col };
+                    let (ny, nx) = (y as isize + dy as isize, x as isize + dx as isize);
+                    to_wall[y * w + x] = if ny < 0 || nx < 0 || ny as usize >= h || nx as usize >= w { 1 } else {
+                        let next = ny as usize * w + nx as usize;
+                        if walls[next] { 0 } else { to_wall[next] + 1 }
+                    };
+                }
+            }
         }
     }
 
-    fn to_idx(&self, cord: Cord) -> usize {
-        (self.width * cord.0 + cord.1) as usize
+    // everything outside of the field counts as wall
+    fn is_wall(&self, cord: Option<Cord>) -> bool {
+        cord.is_none_or(|cord| self.field[cord] == BlockType::Wall)
     }
 
-    fn insert_shape(&mut self, org: Cord, shape: &Vec<(i32, i32)>) {
-        shape.iter().for_each(|off| {
-            let new_cord = org + *off;
-            let i = self.to_idx(new_cord);
-            self.field[i] = BlockType::Wall;
-        });
+    // blocks of the shape that would leave the field are dropped
+    fn insert_shape(&mut self, org: Cord, shape: &[(i32, i32)]) {
+        let added: Vec<Cord> = shape.iter()
+            .filter_map(|off| self.field.offset(org, *off))
+            .filter(|cord| self.field[*cord] != BlockType::Wall)
+            .collect();
+        // the whole shape is in place before the distances are updated, so only its outer tiles walk the open field
+        added.iter().for_each(|cord| self.field[*cord] = BlockType::Wall);
+        for cord in added {
+            DIRS.iter().for_each(|dir| self.update_to_wall(cord, *dir));
+        }
     }
 
     fn insert_spawn(&mut self) {
@@ -88,125 +263,44 @@ impl Field {
             (0, -2), (0, 2),
             (1, -2), (1, -1), (1, 0), (1, 1), (1, 2),
         ];
-        let (my, mx): (u32, u32) = ((self.height / 2), (self.width / 2));
-        let gate_idx = self.to_idx(Cord(my-1, mx));
+        let (my, mx): (u32, u32) = ((self.field.height() / 2), (self.field.width() / 2));
         // delete exising blocks in spawn area
         for y in my - 2..=my + 2 {
             for x in mx - 3..=mx + 3 {
-                let idx = self.to_idx(Cord(y, x));
-                self.field[idx] = BlockType::Empty;
+                self.set(Cord(y, x), BlockType::Empty);
             }
         }
-        self.insert_shape(Cord::from((my, mx)), &spawn_area);
-        self.field[gate_idx] = BlockType::Gate;
+        self.insert_shape(Cord(my, mx), &spawn_area);
+        self.set(Cord(my - 1, mx), BlockType::Gate);
+    }
+    // a solid block for generators that carve their corridors out of it
+    fn fill_walls(&mut self) {
+        self.field.fill(BlockType::Wall);
+        self.rebuild_to_wall();
+    }
+    // the corridor right inside the border, it holds the pellets and the ends of the tunnels
+    fn open_ring(&mut self) {
+        let (h, w) = (self.field.height(), self.field.width());
+        let sides = [Rect::new(Cord(1, 1), 1, w - 2), Rect::new(Cord(h - 2, 1), 1, w - 2), Rect::new(Cord(1, 1), h - 2, 1), Rect::new(Cord(1, w - 2), h - 2, 1)];
+        self.set_many(sides.iter().flat_map(|side| side.cords()), BlockType::PacDot);
     }
     fn init(&mut self) {
-        self.field = vec![BlockType::PacDot; (self.width * self.height) as usize];
-        let w = self.width as usize;
-        let h = self.height as usize;
-        self.field.iter_mut()
-            .enumerate()
-            .for_each(|(i, val)| {
-                let first_or_last_row = i < w || i > ((h - 1) * w);
-                let first_or_last_col = i % w == 0 || i % w == (w - 1);
-                if first_or_last_col || first_or_last_row {
-                    *val = BlockType::Wall;
-                }
-            });
+        self.field.fill(BlockType::PacDot);
+        let (h, w) = (self.field.height(), self.field.width());
+        for border in [Rect::new(Cord(0, 0), 1, w), Rect::new(Cord(h - 1, 0), 1, w), Rect::new(Cord(0, 0), h, 1), Rect::new(Cord(0, w - 1), h, 1)] {
+            self.field.fill_region(border, BlockType::Wall);
+        }
+        self.rebuild_to_wall();
         self.insert_spawn();
     }
 
-    fn get_blocks(&self, mut cord: Cord, dir: &Dir) -> Vec<Cord> {
-        let off = dir.to_tup();
-        let mut cords: Vec<Cord> = Vec::new();
-        while cord.0 < self.height && cord.1 < self.width {
-            cords.push(cord);
-            if cord.0 == 0 || cord.1 == 0 { break; }
-            cord = cord + off;
-        }
-        cords
+    // `cord` and every tile after it in `dir` up to the edge of the field
+    fn ray(&self, cord: Cord, dir: Dir) -> impl Iterator<Item = Cord> + '_ {
+        std::iter::successors(Some(cord).filter(|c| self.field.in_bounds(*c)), move |c| self.field.neighbor(*c, dir))
     }
 
     fn get_distance_to_wall(&self, cord: Cord, dir: &Dir) -> u32 {
-        let blocks = self.get_blocks(cord, dir);
-        blocks.iter().skip(1).position(|block| self.field[self.to_idx(*block)] == BlockType::Wall).unwrap_or(blocks.len()) as u32
-    }
-
-    //max length in one direction -> pos is included
-    fn get_max_dir(&self, cord: Cord, dir: &Dir, for_star: bool) -> u32 {
-        let valid_distance = |block: &Cord| {
-            let perpendicular = dir.get_perpendicular_dirs();
-            let distances = (self.get_distance_to_wall(*block, &perpendicular[0]), self.get_distance_to_wall(*block, &perpendicular[1]));
-            (distances.0 != 0 && distances.1 != 0) && (!for_star || distances.0 != 2 && distances.1 != 2)
-        };
-        let blocks = self.get_blocks(cord, dir);
-        let n = blocks.iter()
-            .position(|block| !valid_distance(block) || self.field[self.to_idx(*block)] == BlockType::Wall)
-            .unwrap_or(0) as u32;
-        if n == 0 { 0 } else { n - 1 }
-    }
-
-    fn get_rand_size(&self, cord: Cord, direction: &Dir, for_star: bool) -> u32 {
-        const PREFFERED_LEN: u32 = 3;
-        let mut rng = thread_rng();
-        let max = cmp::min(self.get_max_dir(cord, &direction, for_star), MAX_WALL_LENGTH);
-        if max == 0 { return 0; }
-        let mut rand: u32;
-        if max > PREFFERED_LEN { rand = rng.gen_range(PREFFERED_LEN..=max); } else { rand = max; };
-        let off = (direction.to_tup().0 * (rand - 1) as i32, direction.to_tup().1 * (rand - 1) as i32);
-
-        if self.get_distance_to_wall(cord + off, direction) == 2 {
-            rand += 1;
-        }
-        rand
-    }
-    fn insert_random_rect(&mut self, cord: Cord) -> u32 {
-        let mut height = self.get_rand_size(cord, &Dir::Down, false);
-        return loop {
-            let (mut max_width, mut off_y) = (u32::MAX, 0);
-            for y in 0..height {
-                let curr_max_width = self.get_max_dir(cord + (y as i32, 0), &Dir::Right, false);
-                if curr_max_width < max_width {
-                    max_width = curr_max_width;
-                    off_y = y;
-                }
-            }
-            if max_width == 0 {
-                height -= 1;
-                continue;
-            }
-            let width = self.get_rand_size(cord + (off_y as i32, 0), &Dir::Right, false);
-            self.insert_shape(cord, &Rectangle { width, height }.block_positions());
-            break width;
-        };
-    }
-
-    fn insert_random_star(&mut self, cord: Cord) -> u32 {
-        let height = self.get_rand_size(cord, &Dir::Down, false);
-
-        let mut distances_per_block: Vec<u32> = Vec::new();
-        for diff_y in 0..height {
-            let max_right = self.get_max_dir(cord + (diff_y as i32, 0), &Dir::Right, true);
-            let max_left = self.get_max_dir(cord + (diff_y as i32, 0), &Dir::Left, true);
-            if (max_right != 1 || max_left != 1) && (max_right != 0 && max_left != 0) {
-                distances_per_block.extend(vec![diff_y; (max_left + max_right) as usize - 1]);
-            }
-        }
-
-        let mut star = Star { height, offset: 0, left: 1, right: 1 };
-        if distances_per_block.len() != 0 {
-            let mut rng = thread_rng();
-            let idx = rng.gen_range(0..distances_per_block.len());
-            let offset = distances_per_block[idx];
-
-            let right = self.get_rand_size(cord + (offset as i32, 0), &Dir::Right, true);
-            let left = self.get_rand_size(cord + (offset as i32, 0), &Dir::Left, true);
-
-            star = Star { height, offset, right, left };
-        }
-        self.insert_shape(cord, &star.block_positions());
-
-        1
+        self.to_wall[*dir as usize][cord]
     }
 
     fn neighbours(&self, cord: Cord) -> u32 {
@@ -214,37 +308,38 @@ impl Field {
         for y in -1..=1 {
             for x in -1..=1 {
                 if y == 0 && x == 0 { continue; }
-                if self.field[self.to_idx(cord + (y, x))] == BlockType::Wall { count += 1; }
+                if self.is_wall(self.field.offset(cord, (y, x))) { count += 1; }
             }
         }
         count
     }
     fn post_processing(&mut self) {
-        for y in 2..self.height - 2 {
-            for x in 2..self.width - 2 {
-                if self.field[self.to_idx(Cord(y, x))] == BlockType::Wall { continue; }
+        for y in 2..self.field.height() - 2 {
+            for x in 2..self.field.width() - 2 {
+                if self.field[Cord(y, x)] == BlockType::Wall { continue; }
 
                 let cords: [Cord; 4] = [Cord(y, x + 1), Cord(y + 1, x + 1), Cord(y, x), Cord(y + 1, x)];
-                if cords.iter().fold(false, |a, b| self.field[self.to_idx(*b)] == BlockType::Wall || a) {
+                if cords.iter().any(|b| self.field[*b] == BlockType::Wall) {
                     continue;
                 }
 
-                let valid_cords = cords.iter().find(|cord| {
+                // the outer corridor stays open, it holds the pellets and the ends of the tunnels
+                let valid_cords = cords.iter().filter(|cord| !self.on_ring(**cord)).find(|cord| {
                     let dirs: Vec<&Dir> = [Dir::Down, Dir::Right, Dir::Left, Dir::Up]
                         .iter()
-                        .filter(|dir| self.field[self.to_idx(**cord + dir.to_tup())] == BlockType::Wall)
+                        .filter(|dir| self.is_wall(self.field.neighbor(**cord, **dir)))
                         .collect();
-                    let mut visited: Vec<Cord> = Vec::new();
+                    let mut visited: Vec<Option<Cord>> = Vec::new();
                     let count: u32 = dirs.iter()
                         .map(|dir|{
                             let perp_dirs = dir.get_perpendicular_dirs();
-                            let new_cord = **cord + dir.to_tup();
+                            let new_cord = self.field.neighbor(**cord, **dir);
 
-                            let cord1 = new_cord + perp_dirs[0].to_tup();
-                            let cord2 = new_cord + perp_dirs[1].to_tup();
+                            let cord1 = new_cord.and_then(|c| self.field.neighbor(c, perp_dirs[0]));
+                            let cord2 = new_cord.and_then(|c| self.field.neighbor(c, perp_dirs[1]));
 
-                            let c = 1 + (!visited.contains(&cord1) && self.field[self.to_idx(cord1)] == BlockType::Wall) as u32
-                                + (!visited.contains(&cord2) && self.field[self.to_idx(cord2)] == BlockType::Wall) as u32;
+                            let c = 1 + (!visited.contains(&cord1) && self.is_wall(cord1)) as u32
+                                + (!visited.contains(&cord2) && self.is_wall(cord2)) as u32;
                             visited.push(cord1); visited.push(cord2);
                             c
                         }).sum();
@@ -252,60 +347,393 @@ impl Field {
                 });
 
                 if let Some(cord) = valid_cords {
-                    let idx = self.to_idx(*cord);
-                    self.field[idx] = BlockType::Wall;
+                    self.set(*cord, BlockType::Wall);
                 }
             }
         }
+        self.close_squares();
+    }
+
+    // walls one tile of every open 2x2 area left, also where a layout meets the outer corridor.
+    // The outer corridor and the spawn area stay open, the tile is one whose wall can't split the maze
+    fn close_squares(&mut self) {
+        let (h, w) = (self.field.height(), self.field.width());
+        let (my, mx) = (h / 2, w / 2);
+        let spawn = Rect::from_corners(Cord(my - 2, mx - 3), Cord(my + 2, mx + 3));
+        for y in 1..h - 2 {
+            for x in 1..w - 2 {
+                let square = [Cord(y, x), Cord(y, x + 1), Cord(y + 1, x), Cord(y + 1, x + 1)];
+                if !square.iter().all(|c| self.is_open(*c)) { continue; }
+                let candidates: Vec<Cord> = square.iter().copied().filter(|c| !self.on_ring(*c) && !spawn.contains(*c)).collect();
+                if let Some(cord) = candidates.iter().find(|c| self.keeps_connected(**c)) {
+                    self.set(*cord, BlockType::Wall);
+                    continue;
+                }
+                // a dead end pocket that would be cut off is walled as well
+                let mut best: Option<(Cord, Vec<Cord>)> = None;
+                for cord in candidates {
+                    let cut = self.cut_off_by(cord);
+                    if cut.iter().any(|c| self.on_ring(*c) || spawn.contains(*c)) { continue; }
+                    if best.as_ref().is_none_or(|(_, other)| cut.len() < other.len()) { best = Some((cord, cut)); }
+                    if best.as_ref().is_some_and(|(_, cut)| cut.is_empty()) { break; }
+                }
+                if let Some((cord, cut)) = best {
+                    self.set(cord, BlockType::Wall);
+                    cut.iter().for_each(|c| self.set(*c, BlockType::Wall));
+                }
+            }
+        }
+    }
+
+    // the corridor right inside the border
+    fn on_ring(&self, cord: Cord) -> bool {
+        let (h, w) = (self.field.height(), self.field.width());
+        cord.0 == 1 || cord.1 == 1 || cord.0 == h - 2 || cord.1 == w - 2
+    }
+
+    fn is_open(&self, cord: Cord) -> bool {
+        !matches!(self.field[cord], BlockType::Wall | BlockType::Gate)
+    }
+
+    // the open tiles outside of the house that can't be reached any more once `cord` is a wall.
+    // Looks around `cord` first, the whole maze is only searched if that can't tell
+    fn cut_off_by(&mut self, cord: Cord) -> Vec<Cord> {
+        if let Some(cut) = self.cut_off_near(cord) { return cut; }
+        let (block, house) = (self.field[cord], self.house());
+        self.field[cord] = BlockType::Wall;
+        let reached = self.reachable();
+        let cut = self.field.cords().filter(|c| !reached[*c] && self.is_open(*c) && !house.contains(*c)).collect();
+        self.field[cord] = block;
+        cut
+    }
+
+    // None if walling `cord` might split off more than CUT_SEARCH tiles
+    fn cut_off_near(&self, cord: Cord) -> Option<Vec<Cord>> {
+        let sides: Vec<Cord> = self.field.neighbors(cord).map(|(_, next)| next).filter(|next| self.is_open(*next)).collect();
+        let (mut grouped, mut cut, mut big_parts) = (Vec::new(), Vec::new(), 0);
+        for side in &sides {
+            if grouped.contains(side) { continue; }
+            let (part, complete) = self.part_without(cord, *side, &sides);
+            grouped.extend(sides.iter().filter(|s| part.contains(*s)));
+            // the outer corridor always belongs to the rest of the maze
+            if complete && !part.iter().any(|c| self.on_ring(*c)) { cut.extend(part); } else { big_parts += 1; }
+        }
+        (big_parts == 1).then_some(cut)
+    }
+
+    // the open tiles connected to `start` when `cord` is a wall and whether that are all of them.
+    // Stops after CUT_SEARCH tiles or once every tile of `sides` is found
+    fn part_without(&self, cord: Cord, start: Cord, sides: &[Cord]) -> (Vec<Cord>, bool) {
+        let mut seen = Grid::new(self.field.width(), self.field.height(), false);
+        seen[start] = true;
+        // the part is its own bfs queue
+        let (mut part, mut head) = (vec![start], 0);
+        while let Some(&tile) = part.get(head) {
+            head += 1;
+            if part.len() > CUT_SEARCH || sides.iter().all(|s| seen[*s]) { return (part, false); }
+            for (_, next) in self.field.neighbors(tile) {
+                if next != cord && self.is_open(next) && !seen[next] {
+                    seen[next] = true;
+                    part.push(next);
+                }
+            }
+        }
+        (part, true)
+    }
+
+    // the open neighbours of `cord` stay connected around it once it is a wall, checked on the 8 tiles around it
+    fn keeps_connected(&self, cord: Cord) -> bool {
+        let open = |off: (i32, i32)| self.field.offset(cord, off).is_some_and(|c| self.is_open(c));
+        // clockwise from the top, every side is followed by the corner after it
+        let ring = [(-1, 0), (-1, 1), (0, 1), (1, 1), (1, 0), (1, -1), (0, -1), (-1, -1)];
+        let sides = (0..4).filter(|i| open(ring[2 * i])).count();
+        let links = (0..4).filter(|i| open(ring[2 * i]) && open(ring[2 * i + 1]) && open(ring[(2 * i + 2) % 8])).count();
+        sides > 0 && (sides - links == 1 || links == 4)
     }
 
     fn insert_pallets(&mut self) {
-        [Cord(1,1), Cord(1,self.width-2), Cord(self.height-2, 1), Cord(self.height-2, self.width-2)].iter()
+        [Cord(1,1), Cord(1,self.field.width()-2), Cord(self.field.height()-2, 1), Cord(self.field.height()-2, self.field.width()-2)].iter()
             .for_each(|cord|{ 
-                let idx = self.to_idx(*cord);
-                self.field[idx] = BlockType::PowerPellet
+                self.set(*cord, BlockType::PowerPellet)
             });
     }
-    fn create_passages(&mut self) {
-        let mut rng = thread_rng();
-        for y in 2..self.width - 2 {
-            let mut x = 2;
-            while x < self.height - 2 {
-                let cord = Cord(y, x);
-                if self.field[self.to_idx(cord)] == BlockType::Wall || self.neighbours(cord) > 0 {
-                    x += 1;
-                    continue;
+    fn cords_of(&self, block: BlockType) -> Vec<Cord> {
+        self.field.iter().filter(|(_, b)| **b == block).map(|(cord, _)| cord).collect()
+    }
+
+    // tiles pacman can walk to from the top left corner
+    fn reachable(&self) -> Grid<bool> {
+        let mut seen = Grid::new(self.field.width(), self.field.height(), false);
+        let mut queue = VecDeque::from([Cord(1, 1)]);
+        seen[Cord(1, 1)] = true;
+        while let Some(cord) = queue.pop_front() {
+            for next in DIRS.iter().filter_map(|dir| self.field.neighbor(cord, *dir)) {
+                if seen[next] || matches!(self.field[next], BlockType::Wall | BlockType::Gate) { continue; }
+                seen[next] = true;
+                queue.push_back(next);
+            }
+        }
+        seen
+    }
+
+    // the house including its walls and the gate
+    fn house(&self) -> Rect {
+        let (my, mx) = (self.field.height() / 2, self.field.width() / 2);
+        Rect::from_corners(Cord(my - 1, mx - 2), Cord(my + 1, mx + 2))
+    }
+
+    // joins every open area pacman can't reach to one he can, through as few walls as possible.
+    // The border and the ghost house are never carved
+    fn connect_regions(&mut self) {
+        let (h, w, house) = (self.field.height(), self.field.width(), self.house());
+        let open = |field: &Grid<BlockType>, cord: Cord| !matches!(field[cord], BlockType::Wall | BlockType::Gate);
+        let carvable = |cord: Cord| cord.0 > 0 && cord.1 > 0 && cord.0 < h - 1 && cord.1 < w - 1 && !house.contains(cord);
+        let mut reached = self.reachable();
+        let mut cost = Grid::new(w, h, u32::MAX);
+        let mut from: Grid<Option<Cord>> = Grid::new(w, h, None);
+        let mut queue = VecDeque::new();
+        // the tiles the last search wrote to, only they have to be reset for the next one
+        let mut touched = Vec::new();
+        for start in self.field.cords() {
+            if reached[start] || !open(&self.field, start) || house.contains(start) { continue; }
+            // 0-1 bfs, open tiles are free and walls cost one
+            for cord in touched.drain(..) {
+                cost[cord] = u32::MAX;
+                from[cord] = None;
+            }
+            queue.clear();
+            cost[start] = 0;
+            touched.push(start);
+            queue.push_back(start);
+            let mut goal = None;
+            while let Some(cord) = queue.pop_front() {
+                if reached[cord] { goal = Some(cord); break; }
+                for (_, next) in self.field.neighbors(cord).filter(|(_, next)| carvable(*next)) {
+                    let step = !open(&self.field, next) as u32;
+                    if cost[cord] + step >= cost[next] { continue; }
+                    cost[next] = cost[cord] + step;
+                    from[next] = Some(cord);
+                    touched.push(next);
+                    if step == 0 { queue.push_front(next); } else { queue.push_back(next); }
+                }
+            }
+            let Some(mut cord) = goal else { continue; };
+            while let Some(prev) = from[cord] {
+                if self.field[prev] == BlockType::Wall { self.set(prev, BlockType::PacDot); }
+                cord = prev;
+            }
+            // the area of `start` and the new corridor are reachable now
+            let mut stack = vec![start];
+            reached[start] = true;
+            while let Some(cord) = stack.pop() {
+                for (_, next) in self.field.neighbors(cord) {
+                    if reached[next] || !open(&self.field, next) { continue; }
+                    reached[next] = true;
+                    stack.push(next);
                 }
+            }
+        }
+    }
 
-                let max_bottom = self.get_max_dir(cord, &Dir::Down, true);
-                let max_right = self.get_max_dir(cord, &Dir::Right, true);
-                let star_valid = max_right > 0 && max_bottom > 0;
+    // open tiles outside the house with a single way out, the gate counts as one
+    fn is_dead_end(&self, cord: Cord) -> bool {
+        !matches!(self.field[cord], BlockType::Wall | BlockType::Gate) && !self.house().contains(cord)
+            && self.field.neighbors(cord).filter(|(_, next)| self.field[*next] != BlockType::Wall).count() == 1
+    }
 
-                let max_bottom = self.get_max_dir(cord, &Dir::Down, false);
-                let max_right = self.get_max_dir(cord, &Dir::Right, false);
-                let rect_valid = max_bottom > 0 && max_right > 0;
+    // inside the border and outside of the house
+    fn carvable(&self, cord: Cord) -> bool {
+        let (h, w) = (self.field.height(), self.field.width());
+        cord.0 > 0 && cord.1 > 0 && cord.0 < h - 1 && cord.1 < w - 1 && !self.house().contains(cord)
+    }
 
-                let rand = rng.gen::<u8>();
-                let is_first_element = y == 2 && x == 2;
+    // opening `cord` together with `path` would give an open 2x2 area
+    fn opens_square(&self, cord: Cord, path: &[Cord]) -> bool {
+        let open = |c: Cord| path.contains(&c) || !matches!(self.field[c], BlockType::Wall | BlockType::Gate);
+        [(-1, -1), (-1, 0), (0, -1), (0, 0)].iter().any(|corner| {
+            cord.checked_add(*corner).is_some_and(|top_left| [(0, 0), (0, 1), (1, 0), (1, 1)].iter()
+                .all(|off| top_left.checked_add(*off).filter(|c| self.field.in_bounds(*c)).is_some_and(|c| c == cord || open(c))))
+        })
+    }
 
-                if (is_first_element || rand < (u8::MAX / 3) || !star_valid) && rect_valid {
-                    x += self.insert_random_rect(cord);
-                } else if star_valid {
-                    x += self.insert_random_star(cord);
-                }
-                x += 1;
+    // depth first over the walls next to the end of `path`, keeps the shortest run that reaches
+    // an open tile without opening a 2x2 area or touching the border or the house
+    fn braid_search(&self, start: Cord, path: &mut Vec<Cord>, dirs: &[Dir], best: &mut Option<Vec<Cord>>) {
+        let house = self.house();
+        let last = path.last().copied().unwrap_or(start);
+        for next in dirs.iter().filter_map(|dir| self.field.neighbor(last, *dir)) {
+            if next == start || path.contains(&next) { continue; }
+            if self.field[next] != BlockType::Wall {
+                let joins = !path.is_empty() && self.field[next] != BlockType::Gate && !house.contains(next);
+                if joins && best.as_ref().is_none_or(|best| path.len() < best.len()) { *best = Some(path.clone()); }
+                continue;
             }
+            let shorter = best.as_ref().is_none_or(|best| path.len() + 1 < best.len());
+            if !self.carvable(next) || !shorter || path.len() == MAX_BRAID_LENGTH || self.opens_square(next, path) { continue; }
+            path.push(next);
+            self.braid_search(start, path, dirs, best);
+            path.pop();
         }
-        self.insert_spawn();
-        self.post_processing();
-        self.insert_pallets();
     }
 
+    // a pellet in a corner of a broken ring can't be joined without a 2x2 area, the corridor next to
+    // the ring is in the way. It moves onto the ring one tile at a time until the pellet can be joined,
+    // gives the tiles next to the moved corridor as they may be dead ends now
+    fn close_corner(&mut self, cord: Cord, dirs: &[Dir]) -> Vec<Cord> {
+        let ring: Vec<(Dir, Cord)> = self.field.neighbors(cord).collect();
+        let (Some((along, _)), Some((inward, _))) = (
+            ring.iter().find(|(_, next)| self.field[*next] == BlockType::Wall && self.carvable(*next)),
+            ring.iter().find(|(_, next)| self.field[*next] != BlockType::Wall),
+        ) else { return Vec::new(); };
+        let (along, inward) = (*along, *inward);
+        let house = self.house();
+        let mut walled = Vec::new();
+        let mut curr = cord;
+        for _ in 0..MAX_BRAID_LENGTH {
+            let Some(next) = self.field.neighbor(curr, along) else { break; };
+            curr = next;
+            let Some(beside) = self.field.neighbor(curr, inward) else { break; };
+            if house.contains(beside) || self.field[beside] == BlockType::Gate { break; }
+            if self.field[beside] != BlockType::Wall {
+                walled.push((beside, self.field[beside]));
+                self.set(beside, BlockType::Wall);
+            }
+            let mut path = None;
+            self.braid_search(cord, &mut Vec::new(), dirs, &mut path);
+            let Some(path) = path else { continue; };
+            path.iter().for_each(|c| self.set(*c, BlockType::PacDot));
+            let around: Vec<Cord> = walled.iter().flat_map(|(c, _)| self.field.neighbors(*c).map(|(_, next)| next)).collect();
+            let reachable = self.reachable();
+            if around.iter().all(|c| self.field[*c] == BlockType::Wall || reachable[*c]) { return around; }
+            path.iter().for_each(|c| self.set(*c, BlockType::Wall));
+        }
+        walled.iter().rev().for_each(|(c, block)| self.set(*c, *block));
+        Vec::new()
+    }
+
+    // joins dead ends to a close corridor until at most `percent` of them are left. A dead end
+    // that can't be joined is filled, its corridor shrinks until it reaches a crossing
+    fn braid(&mut self, rng: &mut dyn RngCore, percent: u32) {
+        let (h, w) = (self.field.height(), self.field.width());
+        let pellets = [Cord(1, 1), Cord(1, w - 2), Cord(h - 2, 1), Cord(h - 2, w - 2)];
+        let mut dead: Vec<Cord> = self.field.cords().filter(|cord| self.is_dead_end(*cord)).collect();
+        dead.shuffle(rng);
+        let mut work = dead.split_off(dead.len() * percent.min(100) as usize / 100);
+        while let Some(cord) = work.pop() {
+            if !self.is_dead_end(cord) { continue; }
+            let mut dirs = DIRS;
+            dirs.shuffle(rng);
+            let mut path = None;
+            self.braid_search(cord, &mut Vec::new(), &dirs, &mut path);
+            if let Some(path) = path {
+                path.iter().for_each(|c| self.set(*c, BlockType::PacDot));
+                continue;
+            }
+            // the pellets and the way out of the house stay open
+            if pellets.contains(&cord) {
+                work.extend(self.close_corner(cord, &dirs));
+                continue;
+            }
+            if self.field.neighbors(cord).any(|(_, next)| self.field[next] == BlockType::Gate) { continue; }
+            let next = self.field.neighbors(cord).find(|(_, next)| self.field[*next] != BlockType::Wall).map(|(_, next)| next);
+            self.set(cord, BlockType::Wall);
+            work.extend(next);
+        }
+    }
+
+    fn meta(&self) -> LevelMeta {
+        let (my, mx) = (self.field.height() / 2, self.field.width() / 2);
+        let ghost_house = self.house();
+        let house_tiles: Vec<Cord> = ghost_house.cords()
+            .filter(|cord| !matches!(self.field[*cord], BlockType::Wall | BlockType::Gate))
+            .collect();
+        let gates = self.cords_of(BlockType::Gate);
+        let ghost_spawn = gates.first().map_or(Cord(my, mx), |gate| Cord(gate.0 + 1, gate.1));
+
+        // the tile below the house unless post processing walled it in, then the closest reachable tile
+        let reachable = self.reachable();
+        let preferred = Cord(my + 2, mx);
+        let pacman_spawn = self.field.cords()
+            .filter(|cord| reachable[*cord] && !ghost_house.contains(*cord))
+            .min_by_key(|cord| cord.0.abs_diff(preferred.0) + cord.1.abs_diff(preferred.1))
+            .unwrap_or(Cord(1, 1));
+
+        let (h, w) = (self.field.height(), self.field.width());
+        LevelMeta {
+            pacman_spawn,
+            ghost_spawn,
+            ghost_house,
+            house_tiles,
+            gates,
+            pellets: self.cords_of(BlockType::PowerPellet),
+            fruit: pacman_spawn,
+            scatter_corners: [Cord(1, 1), Cord(1, w - 2), Cord(h - 2, w - 2), Cord(h - 2, 1)],
+        }
+    }
+    // opens the border at both ends of random rows, the outer corridor connects them to the maze
+    fn insert_tunnels(&mut self, rng: &mut dyn RngCore, count: u32) {
+        let mut rows = tunnel_rows(self.field.height());
+        rows.shuffle(rng);
+        for y in rows.into_iter().take(count as usize) {
+            for x in [0, 1, self.field.width() - 2, self.field.width() - 1] {
+                if self.field[Cord(y, x)] == BlockType::Wall { self.set(Cord(y, x), BlockType::Empty); }
+            }
+        }
+    }
+
+    // the pac-man rules every generator shares: the ghost house in the middle, no open 2x2 areas,
+    // pellets in the corners, no more dead ends than the config allows and its tunnels
+    fn finish(mut self, rng: &mut dyn RngCore, config: &MazeConfig) -> (Grid<BlockType>, LevelMeta) {
+        FINISH_STEPS.iter().for_each(|step| self.finish_step(rng, config, *step));
+        let meta = self.meta();
+        (self.field, meta)
+    }
+
+    fn finish_step(&mut self, rng: &mut dyn RngCore, config: &MazeConfig, step: GenStep) {
+        match step {
+            GenStep::Spawn => self.insert_spawn(),
+            GenStep::PostProcessing => self.post_processing(),
+            GenStep::Pellets => self.insert_pallets(),
+            GenStep::Braid => self.braid(rng, config.dead_ends),
+            GenStep::Tunnels => self.insert_tunnels(rng, config.tunnels),
+            GenStep::Rect | GenStep::Star | GenStep::Layout | GenStep::Done => (),
+        }
+    }
+}
+
+// odd rows that can hold a tunnel, the outer corridors and the rows of the spawn area are left out
+pub fn tunnel_rows(height: u32) -> Vec<u32> {
+    let my = height / 2;
+    (3..height.saturating_sub(3)).step_by(2).filter(|y| *y + 2 < my || *y > my + 2).collect()
 }
 
-pub fn generate(width: u32, height: u32) -> Vec<BlockType> {
-    let mut pf = Field::new(width, height);
-    pf.init();
-    pf.create_passages();
-    pf.field.clone()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    #[test]
+    fn to_wall_follows_every_set() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut field = Field::new(21, 15).unwrap();
+        field.init();
+        for _ in 0..500 {
+            let cord = Cord(rng.gen_range(0..15), rng.gen_range(0..21));
+            field.set(cord, if rng.gen_bool(0.5) { BlockType::Wall } else { BlockType::PacDot });
+        }
+        for _ in 0..20 {
+            let shape: Vec<(i32, i32)> = (0..6).map(|_| (rng.gen_range(-2..=2), rng.gen_range(-2..=2))).collect();
+            field.insert_shape(Cord(rng.gen_range(0..15), rng.gen_range(0..21)), &shape);
+        }
+        let kept = field.to_wall.clone();
+        field.rebuild_to_wall();
+        assert!(kept == field.to_wall);
+        for cord in field.field.cords() {
+            for dir in DIRS {
+                let open = field.ray(cord, dir).skip(1).take_while(|c| field.field[*c] != BlockType::Wall).count() as u32;
+                let edge = field.ray(cord, dir).skip(1).all(|c| field.field[c] != BlockType::Wall);
+                assert_eq!(field.get_distance_to_wall(cord, &dir), open + edge as u32, "{:?} {}", cord, dir as u8);
+            }
+        }
+    }
 }
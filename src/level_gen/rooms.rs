@@ -0,0 +1,126 @@
+use crate::common::{Cord, BlockType};
+use crate::error::PacmanError;
+use crate::grid::{Grid, Rect};
+use rand::{Rng, RngCore};
+use std::cmp;
+use super::{build, Field, GenStep, Layout, LevelMeta, MazeConfig, MazeGenerator, Progress};
+
+// pillared rooms joined by one tile wide corridors, all inside a corridor along the border.
+// Everything is carved on odd rows or columns, so no open 2x2 area can appear
+pub struct Rooms;
+
+// maze tiles per room
+const ROOM_AREA: u32 = 80;
+const TRIES_PER_ROOM: u32 = 4;
+// rooms the layout tries to place in one step
+const TRIES_PER_STEP: u32 = 512;
+
+// the closest odd tile at or after `value`
+fn odd(value: u32) -> u32 {
+    value | 1
+}
+
+fn center(room: &Rect) -> Cord {
+    let end = room.end();
+    Cord(odd((room.top_left.0 + end.0 - 1) / 2), odd((room.top_left.1 + end.1 - 1) / 2))
+}
+
+// the room with a ring of one tile around it
+fn padded(room: &Rect) -> Rect {
+    Rect::from_corners(room.top_left.saturating_add((-1, -1)), room.end())
+}
+
+fn overlaps(a: &Rect, b: &Rect) -> bool {
+    let (a_end, b_end) = (a.end(), b.end());
+    a.top_left.0 < b_end.0 && b.top_left.0 < a_end.0 && a.top_left.1 < b_end.1 && b.top_left.1 < a_end.1
+}
+
+impl Field {
+    // opens every tile of `region` that has an odd coordinate, the others stay as pillars
+    fn carve_room(&mut self, region: Rect) {
+        for cord in region.cords() {
+            if cord.0 % 2 == 1 || cord.1 % 2 == 1 { self.set(cord, BlockType::PacDot); }
+        }
+    }
+
+    // along the row of `from` to the column of `to`, then along that column, both have to be odd
+    fn carve_corridor(&mut self, from: Cord, to: Cord) {
+        let (left, right) = (cmp::min(from.1, to.1), cmp::max(from.1, to.1));
+        self.carve_room(Rect::from_corners(Cord(from.0, left), Cord(from.0, right)));
+        let (top, bottom) = (cmp::min(from.0, to.0), cmp::max(from.0, to.0));
+        self.carve_room(Rect::from_corners(Cord(top, to.1), Cord(bottom, to.1)));
+    }
+
+    // odd sized rooms at odd positions, away from the border corridor and from each other
+    fn random_room(&self, rng: &mut dyn RngCore) -> Option<Rect> {
+        let (h, w) = (self.field.height(), self.field.width());
+        let (height, width) = (2 * rng.gen_range(2..=4) - 1, 2 * rng.gen_range(2..=5) - 1);
+        if height + 6 > h || width + 6 > w { return None; }
+        let top = 3 + 2 * rng.gen_range(0..=(h - 6 - height) / 2);
+        let left = 3 + 2 * rng.gen_range(0..=(w - 6 - width) / 2);
+        Some(Rect::new(Cord(top, left), height, width))
+    }
+
+    // the corridor from `cord` straight to the closest side of the border corridor
+    fn carve_to_border(&mut self, cord: Cord) {
+        let (h, w) = (self.field.height(), self.field.width());
+        let targets = [Cord(1, cord.1), Cord(h - 2, cord.1), Cord(cord.0, 1), Cord(cord.0, w - 2)];
+        if let Some(target) = targets.iter().min_by_key(|t| t.0.abs_diff(cord.0) + t.1.abs_diff(cord.1)) {
+            self.carve_corridor(cord, *target);
+        }
+    }
+}
+
+impl Layout for Rooms {
+    // rooms are placed TRIES_PER_STEP at a time, then every step carves one of them
+    fn layout_step(&self, rng: &mut dyn RngCore, field: &mut Field, progress: &mut Progress) -> Result<GenStep, PacmanError> {
+        let (h, w) = (field.field.height(), field.field.width());
+        // the spawn area is cleared by finish, it only needs to be connected
+        let (my, mx) = (h / 2, w / 2);
+        let spawn = Rect::from_corners(Cord(my - 2, mx - 3), Cord(my + 2, mx + 3));
+        match progress {
+            Progress::Start => {
+                field.fill_walls();
+                field.open_ring();
+                *progress = Progress::Placing(vec![spawn], cmp::max(w * h / ROOM_AREA, 1) * TRIES_PER_ROOM);
+            },
+            Progress::Placing(rooms, tries) => {
+                let target = cmp::max(w * h / ROOM_AREA, 1) as usize;
+                for _ in 0..cmp::min(*tries, TRIES_PER_STEP) {
+                    *tries -= 1;
+                    if rooms.len() > target { *tries = 0; break; }
+                    let Some(room) = field.random_room(rng) else { continue; };
+                    if rooms.iter().any(|other| overlaps(&padded(&room), other)) { continue; }
+                    rooms.push(room);
+                }
+                if *tries == 0 {
+                    field.carve_to_border(center(&spawn));
+                    *progress = Progress::Rooms(std::mem::take(rooms), 1);
+                }
+            },
+            Progress::Rooms(rooms, i) => {
+                let Some(room) = rooms.get(*i).copied() else {
+                    *progress = Progress::Done;
+                    return Ok(GenStep::Done);
+                };
+                field.carve_room(room);
+                // every room joins the closest one placed before it, some also get a way out to the border
+                let closest = rooms[..*i].iter()
+                    .map(center)
+                    .min_by_key(|c| c.0.abs_diff(center(&room).0) + c.1.abs_diff(center(&room).1))
+                    .unwrap_or(center(&spawn));
+                field.carve_corridor(center(&room), closest);
+                if rng.gen_bool(0.5) { field.carve_to_border(center(&room)); }
+                *i += 1;
+            },
+            _ => return Ok(GenStep::Done),
+        }
+        Ok(GenStep::Layout)
+    }
+}
+
+impl MazeGenerator for Rooms {
+    fn generate(&self, rng: &mut dyn RngCore, config: &MazeConfig) -> Result<(Grid<BlockType>, LevelMeta), PacmanError> {
+        build(self, rng, config)
+    }
+}
@@ -0,0 +1,307 @@
+use crate::common::{Cord, BlockType};
+use crate::error::PacmanError;
+use crate::grid::Grid;
+use crate::movement::DIRS;
+use rand::{Rng, RngCore};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+use super::{build, Field, GenStep, Layout, LevelMeta, MazeConfig, MazeGenerator, Progress};
+
+// width and height of the learned patterns
+const N: usize = 3;
+const WORD: usize = u64::BITS as usize;
+// words of a set of patterns, there are at most 2^(N*N) of them
+const MAX_WORDS: usize = (1 << (N * N)) / WORD;
+// cells looked at in one step of the layout
+const CELLS_PER_STEP: usize = 2048;
+
+// the arcade maze without the ghost house and the tunnels
+pub const DEFAULT_SAMPLE: &str = "\
+############################
+#............##............#
+#.####.#####.##.#####.####.#
+#.####.#####.##.#####.####.#
+#.####.#####.##.#####.####.#
+#..........................#
+#.####.##.########.##.####.#
+#.####.##.########.##.####.#
+#......##....##....##......#
+######.#####.##.#####.######
+######.#####.##.#####.######
+######.##..........##.######
+######.##.########.##.######
+######.##.########.##.######
+#.........########.........#
+######.##.########.##.######
+######.##.########.##.######
+######.##..........##.######
+######.##.########.##.######
+######.##.########.##.######
+#............##............#
+#.####.#####.##.#####.####.#
+#.####.#####.##.#####.####.#
+#...##................##...#
+###.##.##.########.##.##.###
+###.##.##.########.##.##.###
+#......##....##....##......#
+#.##########.##.##########.#
+#.##########.##.##########.#
+#..........................#
+############################";
+
+// learns the 3x3 wall patterns of sample mazes and fills new mazes with them (overlapping wave function collapse).
+// Samples are ASCII boards where `#` is a wall and everything else is open, several samples are separated by empty lines
+#[derive(Clone, Debug)]
+pub struct Wfc {
+    // bit y * N + x is set for a wall
+    patterns: Vec<u16>,
+    // how often each pattern showed up in the samples
+    weights: Vec<u32>,
+    // per Dir and pattern the set of patterns that fit one cell further in that dir
+    allowed: [Vec<Vec<u64>>; 4],
+    words: usize,
+}
+
+fn bit(pattern: u16, y: usize, x: usize) -> bool {
+    pattern & (1 << (y * N + x)) != 0
+}
+
+// the pattern with every pixel moved by `f`
+fn map_pattern(pattern: u16, f: impl Fn(usize, usize) -> (usize, usize)) -> u16 {
+    let mut out = 0;
+    for y in 0..N {
+        for x in 0..N {
+            let (ny, nx) = f(y, x);
+            if bit(pattern, y, x) { out |= 1 << (ny * N + nx); }
+        }
+    }
+    out
+}
+
+// the four rotations and their mirror images
+fn symmetries(pattern: u16) -> [u16; 8] {
+    let rotate = |p: u16| map_pattern(p, |y, x| (x, N - 1 - y));
+    let mirror = |p: u16| map_pattern(p, |y, x| (y, N - 1 - x));
+    let mut out = [pattern; 8];
+    for i in 1..4 { out[i] = rotate(out[i - 1]); }
+    for i in 4..8 { out[i] = mirror(out[i - 4]); }
+    out
+}
+
+// `next` placed one cell away from `pattern` by (dy, dx) agrees on every pixel they share
+fn agrees(pattern: u16, next: u16, (dy, dx): (i32, i32)) -> bool {
+    (0..N).all(|y| (0..N).all(|x| {
+        let (ny, nx) = (y as i32 - dy, x as i32 - dx);
+        let shared = (0..N as i32).contains(&ny) && (0..N as i32).contains(&nx);
+        !shared || bit(pattern, y, x) == bit(next, ny as usize, nx as usize)
+    }))
+}
+
+fn parse_sample(rows: &[&str]) -> Result<Vec<Vec<bool>>, PacmanError> {
+    let walls: Vec<Vec<bool>> = rows.iter().map(|row| row.chars().map(|c| c == '#').collect()).collect();
+    let width = walls[0].len();
+    if walls.len() < N || width < N || walls.iter().any(|row| row.len() != width) { return Err(PacmanError::InvalidSample); }
+    Ok(walls)
+}
+
+impl Wfc {
+    pub fn from_ascii(samples: &str) -> Result<Self, PacmanError> {
+        // ordered so the same samples always give the same pattern indices
+        let mut counts: BTreeMap<u16, u32> = BTreeMap::new();
+        let lines: Vec<&str> = samples.lines().map(|line| line.trim_end_matches('\r')).collect();
+        for rows in lines.split(|line| line.trim().is_empty()).filter(|rows| !rows.is_empty()) {
+            let walls = parse_sample(rows)?;
+            for y in 0..=walls.len() - N {
+                for x in 0..=walls[0].len() - N {
+                    let pattern = (0..N * N).filter(|i| walls[y + i / N][x + i % N]).fold(0, |p, i| p | 1 << i);
+                    symmetries(pattern).iter().for_each(|p| *counts.entry(*p).or_default() += 1);
+                }
+            }
+        }
+        if counts.is_empty() { return Err(PacmanError::InvalidSample); }
+
+        let (patterns, weights): (Vec<u16>, Vec<u32>) = counts.into_iter().unzip();
+        let words = patterns.len().div_ceil(WORD);
+        let allowed = DIRS.map(|dir| patterns.iter()
+            .map(|p| {
+                let mut set = vec![0; words];
+                for (i, q) in patterns.iter().enumerate() {
+                    if agrees(*p, *q, dir.to_tup()) { set[i / WORD] |= 1 << (i % WORD); }
+                }
+                set
+            })
+            .collect());
+        Ok(Self { patterns, weights, allowed, words })
+    }
+
+    // the arcade look, used by MazeKind::Wfc
+    pub fn arcade() -> &'static Wfc {
+        static ARCADE: OnceLock<Wfc> = OnceLock::new();
+        ARCADE.get_or_init(|| Wfc::from_ascii(DEFAULT_SAMPLE).expect("the default sample is valid"))
+    }
+
+    fn ones(set: &[u64]) -> impl Iterator<Item = usize> + '_ {
+        set.iter().enumerate().flat_map(|(i, word)| {
+            std::iter::successors(Some(*word).filter(|w| *w != 0), |w| Some(w & (w - 1)).filter(|w| *w != 0))
+                .map(move |w| i * WORD + w.trailing_zeros() as usize)
+        })
+    }
+
+    // everything allowed next to any of `options`, one set per Dir
+    fn unions(&self, options: &[u64]) -> Vec<u64> {
+        let mut out = vec![0; 4 * self.words];
+        for dir in DIRS {
+            let union = &mut out[dir as usize * self.words..(dir as usize + 1) * self.words];
+            for p in Self::ones(options) {
+                union.iter_mut().zip(&self.allowed[dir as usize][p]).for_each(|(u, a)| *u |= a);
+            }
+        }
+        out
+    }
+
+    // every cell can still take every pattern
+    fn start_collapse(&self, height: u32, width: u32) -> Collapse {
+        let (rows, cols, words) = (height as usize - N + 1, width as usize - N + 1, self.words);
+        let mut all = vec![0u64; words];
+        (0..self.patterns.len()).for_each(|i| all[i / WORD] |= 1 << (i % WORD));
+        let wave = all.repeat(rows * cols);
+        let mut buckets: Vec<Vec<usize>> = vec![Vec::new(); self.patterns.len() + 1];
+        buckets[self.patterns.len()] = (0..rows * cols).collect();
+        let lowest = self.patterns.len();
+        Collapse { width, height, rows, cols, wave, buckets, lowest, stack: Vec::new(), union_of: HashMap::new(), unions: Vec::new() }
+    }
+
+    // picks a pattern for the cell with the fewest options left and passes the change on to the cells around it,
+    // until `work` cells were looked at or every cell has a pattern (false).
+    // A cell that would lose its last option keeps its old ones, the seam is fixed by post processing
+    fn collapse(&self, rng: &mut dyn RngCore, state: &mut Collapse, work: usize) -> bool {
+        let Collapse { rows, cols, ref mut wave, ref mut buckets, ref mut lowest, ref mut stack, ref mut union_of, ref mut unions, .. } = *state;
+        let words = self.words;
+        let count = |wave: &[u64], cell: usize| wave[cell * words..(cell + 1) * words].iter().map(|w| w.count_ones()).sum::<u32>();
+        let mut done = 0;
+        loop {
+            // changes left over from the last call are passed on before the next cell is picked
+            while let Some(cell) = stack.pop() {
+                let (y, x) = (cell / cols, cell % cols);
+                let options = &wave[cell * words..(cell + 1) * words];
+                let mut key = [0; MAX_WORDS];
+                key[..words].copy_from_slice(options);
+                let start = match union_of.get(&key) {
+                    Some(start) => *start,
+                    None => {
+                        union_of.insert(key, unions.len());
+                        unions.extend(self.unions(options));
+                        unions.len() - 4 * words
+                    }
+                };
+                let allowed = &unions[start..start + 4 * words];
+                for dir in DIRS {
+                    let (dy, dx) = dir.to_tup();
+                    let (ny, nx) = (y as i32 + dy, x as i32 + dx);
+                    if ny < 0 || nx < 0 || ny as usize >= rows || nx as usize >= cols { continue; }
+                    let next = ny as usize * cols + nx as usize;
+
+                    let union = &allowed[dir as usize * words..(dir as usize + 1) * words];
+                    let next_set = &mut wave[next * words..(next + 1) * words];
+                    let empty = next_set.iter().zip(union).all(|(n, u)| n & u == 0);
+                    let changed = next_set.iter().zip(union).any(|(n, u)| n & u != *n);
+                    if empty || !changed { continue; }
+                    next_set.iter_mut().zip(union).for_each(|(n, u)| *n &= u);
+                    let options = count(wave, next) as usize;
+                    buckets[options].push(next);
+                    *lowest = (*lowest).min(options);
+                    stack.push(next);
+                }
+                done += 1;
+                if done >= work { return true; }
+            }
+
+            let cell = loop {
+                if *lowest >= buckets.len() { return false; }
+                if buckets[*lowest].is_empty() { *lowest += 1; continue; }
+                let idx = rng.gen_range(0..buckets[*lowest].len());
+                let cell = buckets[*lowest].swap_remove(idx);
+                if *lowest > 1 && count(wave, cell) == *lowest as u32 { break cell; }
+                done += 1;
+                if done >= work { return true; }
+            };
+            let cell_set = &mut wave[cell * words..(cell + 1) * words];
+            let total: u32 = Self::ones(cell_set).map(|i| self.weights[i]).sum();
+            let mut pick = rng.gen_range(0..total);
+            let mut chosen = 0;
+            for i in Self::ones(cell_set) {
+                if pick < self.weights[i] { chosen = i; break; }
+                pick -= self.weights[i];
+            }
+            cell_set.fill(0);
+            cell_set[chosen / WORD] |= 1 << (chosen % WORD);
+            stack.push(cell);
+        }
+    }
+
+    // every tile shows the top left pixel of its cell, the last rows and columns come from the last cells
+    fn walls(&self, state: &Collapse) -> Grid<bool> {
+        let (rows, cols, words) = (state.rows, state.cols, self.words);
+        let mut walls = Grid::new(state.width, state.height, true);
+        for cord in walls.cords() {
+            let (cy, cx) = ((cord.0 as usize).min(rows - 1), (cord.1 as usize).min(cols - 1));
+            let cell = cy * cols + cx;
+            let pattern = Self::ones(&state.wave[cell * words..(cell + 1) * words]).next().map_or(u16::MAX, |i| self.patterns[i]);
+            walls[cord] = bit(pattern, cord.0 as usize - cy, cord.1 as usize - cx);
+        }
+        walls
+    }
+}
+
+// a wave function collapse in progress, the part of the field inside the outer corridor
+pub(super) struct Collapse {
+    width: u32,
+    height: u32,
+    rows: usize,
+    cols: usize,
+    // per cell the set of patterns it can still take
+    wave: Vec<u64>,
+    // cells by the number of options they have left, stale entries are skipped when they come up.
+    // Cells are taken from a bucket at random so the maze doesn't grow from one corner
+    buckets: Vec<Vec<usize>>,
+    lowest: usize,
+    // cells whose options changed, the cells around them still have to follow
+    stack: Vec<usize>,
+    // the sets allowed next to a set of options for all 4 dirs, the same sets come up over and over
+    // keyed without an allocation per set, dropping tens of thousands of them took milliseconds
+    union_of: HashMap<[u64; MAX_WORDS], usize>,
+    unions: Vec<u64>,
+}
+
+impl Layout for Wfc {
+    fn layout_step(&self, rng: &mut dyn RngCore, field: &mut Field, progress: &mut Progress) -> Result<GenStep, PacmanError> {
+        let (h, w) = (field.field.height(), field.field.width());
+        match progress {
+            Progress::Start => {
+                field.fill_walls();
+                field.open_ring();
+                // the learned layout fills everything inside the ring
+                *progress = Progress::Collapsing(self.start_collapse(h - 4, w - 4));
+            },
+            Progress::Collapsing(state) => {
+                if self.collapse(rng, state, CELLS_PER_STEP) { return Ok(GenStep::Layout); }
+                let open: Vec<Cord> = self.walls(state).iter().filter(|(_, wall)| !**wall).map(|(cord, _)| Cord(cord.0 + 2, cord.1 + 2)).collect();
+                field.set_many(open, BlockType::PacDot);
+                *progress = Progress::Connecting;
+            },
+            Progress::Connecting => {
+                field.insert_spawn();
+                field.connect_regions();
+                *progress = Progress::Done;
+            },
+            _ => return Ok(GenStep::Done),
+        }
+        Ok(GenStep::Layout)
+    }
+}
+
+impl MazeGenerator for Wfc {
+    fn generate(&self, rng: &mut dyn RngCore, config: &MazeConfig) -> Result<(Grid<BlockType>, LevelMeta), PacmanError> {
+        build(self, rng, config)
+    }
+}
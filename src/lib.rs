@@ -1,57 +1,135 @@
 /*
 
-TODO:   Smooth animation, render with pacman assets
-        Case where a 2 Block wide passage is created around the spawn area
+TODO:   Case where a 2 Block wide passage is created around the spawn area
 
 */
-mod common;
-mod level_gen;
+pub mod common;
+pub mod level_gen;
 mod ghosts;
 mod pacman;
+mod schedule;
+mod speed;
+mod fruit;
+pub mod events;
+pub mod collision;
+pub mod movement;
+pub mod nav;
+pub mod distance;
+pub mod config;
+pub mod error;
+pub mod grid;
+pub mod bitboard;
 
 
 use common::Level;
 use pacman::Pacman;
-use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::prelude::{wasm_bindgen, JsError};
 use ghosts::Ghost;
+use schedule::ModeSchedule;
+use nav::NavGraph;
+use distance::{DangerMap, DistanceField};
+use events::GameEvent;
+use fruit::Fruit;
+use collision::Contact;
 use common::BlockType;
 use common::Cord;
-use crate::common::{Difficulty, Dir,Color};
+use config::{ConfigError, GameConfig};
+use error::PacmanError;
+use movement::Mover;
+use level_gen::{LevelBuilder, MazeGenerator, MazeKind, Wfc};
+use rand::{RngCore, SeedableRng, rngs::StdRng};
+use crate::common::{DifficultyProfile, Dir, Phase, TICKS_PER_SECOND, SUBTILES};
 
 #[wasm_bindgen]
 pub struct Game {
     level: Level,
     pacman: Pacman,
     ghosts: Vec<Ghost>,
-    diff: Difficulty,
+    config: GameConfig,
+    diff: DifficultyProfile,
+    round: u32,
+    schedule: ModeSchedule,
+    nav: NavGraph,
+    dist: DistanceField,
+    // built on request, e.g. for a debug overlay
+    danger: DangerMap,
+    // drives the maze generators, seeded once so a seed gives the same levels
+    rng: StdRng,
+    // learned from the config's samples
+    wfc: Option<Wfc>,
     score: u32,
+    phase: Phase,
+    phase_ticks: u32,
+    events: Vec<GameEvent>,
+    // ghosts eaten with the current power pellet, each one doubles the points
+    ghost_combo: u32,
+    flashing: bool,
+    fruit: Option<Fruit>,
     game_over: bool,
 }
 
-const SCORE_PACDOT: u32 = 10;
-const SCORE_PALLET: u32 = 50;
-const SCORE_GHOST: u32 = 200;
+#[wasm_bindgen]
+pub fn ticks_per_second() -> u32 {
+    TICKS_PER_SECOND
+}
+
+#[wasm_bindgen]
+pub fn subtiles() -> i32 {
+    SUBTILES
+}
 
 #[wasm_bindgen]
 impl Game{
-    pub fn new(width: u32, height: u32, diff: Difficulty) -> Self {
-        let level = Level::new(width, height);
-        let (my, mx) = (level.height()/2, level.width() / 2);
-        let g: Ghost = Ghost::new(0,Cord(my,mx),Color::Cyan,diff);
-        let ghosts = vec![Ghost {color: Color::Red, ..g }, Ghost {color: Color::Pink, id: 1, ..g },
-            Ghost {color: Color::Cyan, id: 2, ..g }, Ghost {color: Color::Orange, id: 3, ..g },
-        ];
-
-        let pac_start_pos = Cord (my + 2, mx);
-        let pacman = Pacman::new(pac_start_pos, 3);
-        Self {
-            level,
-            ghosts,
-            pacman,
-            diff,
-            score: 0,
-            game_over: false,
+    #[wasm_bindgen(js_name = new)]
+    pub fn js_new(width: u32, height: u32, diff: DifficultyProfile) -> Result<Game, JsError> {
+        Ok(Self::new(width, height, diff)?)
+    }
+
+    #[wasm_bindgen(js_name = from_config)]
+    pub fn js_from_config(config: &GameConfig) -> Result<Game, JsError> {
+        Ok(Self::from_config(config.clone())?)
+    }
+
+    fn set_phase(&mut self, phase: Phase, ticks: u32) {
+        match phase {
+            Phase::Dying => self.events.push(GameEvent::PacmanDied),
+            Phase::LevelComplete => self.events.push(GameEvent::LevelCleared),
+            Phase::GameOver => self.events.push(GameEvent::GameOver),
+            Phase::Ready | Phase::Playing => (),
+        }
+        self.phase = phase;
+        self.phase_ticks = ticks;
+    }
+
+    fn add_score(&mut self, points: u32) {
+        let extra_life = self.config.score_extra_life;
+        if extra_life > 0 && self.score / extra_life < (self.score + points) / extra_life {
+            self.pacman.gain_life();
+            self.events.push(GameEvent::LifeGained);
         }
+        self.score += points;
+    }
+
+    #[wasm_bindgen(js_name = next_level)]
+    pub fn js_next_level(&mut self) -> Result<(), JsError> {
+        Ok(self.next_level()?)
+    }
+
+    #[wasm_bindgen(js_name = next_level_builder)]
+    pub fn js_next_level_builder(&mut self) -> Result<LevelBuilder, JsError> {
+        Ok(self.next_level_builder()?)
+    }
+
+    #[wasm_bindgen(js_name = next_level_from)]
+    pub fn js_next_level_from(&mut self, builder: LevelBuilder) -> Result<(), JsError> {
+        Ok(self.next_level_from(builder)?)
+    }
+
+    // pacman and the ghosts go back to their start after pacman lost a life
+    fn reset_positions(&mut self) {
+        self.pacman.reset();
+        self.ghosts.iter_mut().for_each(|ghost| ghost.reset());
+        self.fruit = None;
     }
 
     pub fn set_dir(&mut self, key_code: &str) {
@@ -59,77 +137,148 @@ impl Game{
         self.pacman.set_dir_outside(dir);
     }
 
+    // how many ticks a turn pressed too early is remembered, 0 keeps it until the next key
+    pub fn set_turn_buffer(&mut self, ticks: u32) {
+        self.pacman.set_turn_buffer(ticks);
+    }
+
 
-    fn process_block(&mut self) -> bool {
+    fn process_block(&mut self) -> Result<(), PacmanError> {
         let pac_block = self.level.block(self.pacman.data.cord);
         let score_gained: u32 = match pac_block {
-            BlockType::PacDot => SCORE_PACDOT,
+            BlockType::PacDot => {
+                self.events.push(GameEvent::DotEaten);
+                self.config.score_dot
+            },
             BlockType::PowerPellet => {
                 self.ghosts.iter_mut().for_each(|ghost| ghost.set_vulnerable());
-                SCORE_PALLET
+                self.ghost_combo = 0;
+                self.flashing = false;
+                self.events.push(GameEvent::PelletEaten);
+                if self.diff.frightened_time > 0 { self.events.push(GameEvent::FrightenedStarted); }
+                self.config.score_pellet
             },
             _ => 0
         };
-        if score_gained > 0 {self.level.reduce_dot_count();}
+        if score_gained > 0 {
+            self.level.reduce_dot_count()?;
+            let eaten = self.level.total_dots() - self.level.dot_count();
+            if let Some(fruit) = Fruit::spawn(self.level.meta().fruit, self.round, eaten, self.level.total_dots()) {
+                self.events.push(GameEvent::FruitSpawned { points: fruit.points });
+                self.fruit = Some(fruit);
+            }
+        }
         self.level.set_block(self.pacman.data.cord, BlockType::Empty);
-        self.score += score_gained;
+        self.add_score(score_gained);
 
-        for ghost in self.ghosts.iter_mut() {
-            let ate_pacman = ghost.data.prev_cord == self.pacman.data.cord && self.pacman.data.prev_cord == ghost.data.cord;
-
-            if ate_pacman || ghost.data.cord == self.pacman.data.cord {
-                if ghost.vulnerable() {
-                    ghost.die();
-                    self.score += SCORE_GHOST;
-                } else {
-                    if !self.pacman.revive() {self.game_over = true};
-                    break;
-                }
-            }
+        if let Some(fruit) = self.fruit.filter(|fruit| fruit.cord == self.pacman.data.cord) {
+            self.fruit = None;
+            self.events.push(GameEvent::FruitEaten { points: fruit.points });
+            self.add_score(fruit.points);
         }
-        self.game_over
+
+        let contacts: Vec<Contact> = self.ghosts.iter()
+            .enumerate()
+            .filter(|(_, ghost)| collision::contact(&self.level, &ghost.data, &self.pacman.data))
+            .map(|(ghost, g)| Contact { ghost, id: g.id, vulnerable: g.vulnerable() })
+            .collect();
+        let resolution = collision::resolve(&contacts);
+        for i in resolution.eaten {
+            let ghost = &mut self.ghosts[i];
+            ghost.die();
+            let points = collision::ghost_points(self.config.score_ghost, self.ghost_combo);
+            self.ghost_combo += 1;
+            self.events.push(GameEvent::GhostEaten { id: ghost.id, points });
+            self.add_score(points);
+        }
+        if resolution.pacman_dies {
+            self.set_phase(Phase::Dying, self.config.dying_time);
+            return Ok(());
+        }
+        if self.level.dot_count() == 0 {
+            self.set_phase(Phase::LevelComplete, self.config.level_complete_time);
+        }
+        Ok(())
     }
 
-    pub fn tick(&mut self) -> bool {
-        if self.game_over {return self.game_over;}
+    #[wasm_bindgen(js_name = tick)]
+    pub fn js_tick(&mut self) -> Result<bool, JsError> {
+        Ok(self.tick()?)
+    }
+
+    fn play_tick(&mut self) -> Result<(), PacmanError> {
         //move pacman
-        self.pacman.tick(&self.level);
+        let frightened = self.ghosts.iter().any(|ghost| ghost.vulnerable());
+        self.pacman.tick(&self.level, frightened);
+
+        //the schedule is paused while the ghosts are frightened, every switch reverses them
+        if !self.ghosts.iter().any(|ghost| ghost.vulnerable()) && self.schedule.tick() {
+            self.ghosts.iter_mut().for_each(|ghost| ghost.reverse());
+        }
+
+        //one distance map from pacman shared by all ghosts, only built if their AI reads it
+        if self.ghosts.iter().any(|ghost| ghost.reads_distances()) {
+            self.dist.update(&self.level, self.pacman.data.cord);
+        }
+        if self.ghosts.iter().any(|ghost| ghost.reads_distances() && ghost.vulnerable()) {
+            self.dist.update_flee(&self.level);
+        }
 
         //move/tick ghosts
         for ghost in self.ghosts.iter_mut() {
-            ghost.tick(self.pacman.data.cord,&self.level);
+            ghost.tick(&self.pacman.data,&self.level,self.schedule.mode(),&self.dist);
         }
 
-        self.process_block();
+        if let Some(fruit) = self.fruit.as_mut() {
+            if !fruit.tick() { self.fruit = None; }
+        }
+        let flashing = self.ghosts.iter().any(|ghost| ghost.flashing());
+        if flashing && !self.flashing { self.events.push(GameEvent::FrightenedEnding); }
+        self.flashing = flashing;
 
-        self.game_over
+        self.process_block()
     }
 
-    pub fn ghosts(&self) -> js_sys::Uint32Array {
-        let v: Vec<[u32; 8]> = self.ghosts.iter()
+    pub fn ghosts(&self) -> js_sys::Int32Array {
+        let v: Vec<[i32; 11]> = self.ghosts.iter()
             .map(|g| g.parse_for_fe())
             .collect();
-        let f: Vec<u32> = v.iter()
+        let f: Vec<i32> = v.iter()
             .flatten()
-            .map(|i| *i)
+            .copied()
             .collect();
 
-        js_sys::Uint32Array::from(&f[..])
+        js_sys::Int32Array::from(&f[..])
     }
-    pub fn pacman(&self) -> js_sys::Uint32Array {
-        js_sys::Uint32Array::from(&self.pacman.parse_for_fe()[..])
+    pub fn pacman(&self) -> js_sys::Int32Array {
+        js_sys::Int32Array::from(&self.pacman.parse_for_fe()[..])
     }
 
-    pub fn diff(&self) -> Difficulty {
+    pub fn diff(&self) -> DifficultyProfile {
         self.diff
     }
 
+    pub fn round(&self) -> u32 {
+        self.round
+    }
+
+    pub fn maze_kind(&self) -> MazeKind {
+        self.config.maze_kind(self.round)
+    }
+
     pub fn score(&self) -> u32 {
         self.score
     }
     pub fn game_over(&self) -> bool {
         self.game_over
     }
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+    // ticks until the current phase ends, 0 for Playing and GameOver
+    pub fn phase_ticks(&self) -> u32 {
+        self.phase_ticks
+    }
     pub fn field(&self) -> *const BlockType {
         self.level.field()
     }
@@ -146,6 +295,338 @@ impl Game{
         self.pacman.lives()
     }
     pub fn field_at(&self, y: u32, x: u32) -> BlockType {
-        self.level.block(Cord(y,x))
+        // anything outside of the maze is wall, the frontend may ask for cells around it
+        self.level.grid().get(Cord(y, x)).copied().unwrap_or(BlockType::Wall)
+    }
+    pub fn nav_graph(&self) -> NavGraph {
+        self.nav.clone()
+    }
+    // steps from every tile to the closest ghost that isn't frightened, row by row
+    #[wasm_bindgen(js_name = dangerMap)]
+    pub fn js_danger_map(&mut self) -> js_sys::Uint32Array {
+        js_sys::Uint32Array::from(self.danger().map().as_slice())
+    }
+    // [y, x, points] of the fruit, empty if there is none
+    pub fn fruit(&self) -> js_sys::Uint32Array {
+        let v: Vec<u32> = self.fruit.iter().flat_map(|f| [f.cord.0, f.cord.1, f.points]).collect();
+        js_sys::Uint32Array::from(&v[..])
+    }
+    // [kind, ghost id, points] per event since the last call
+    #[wasm_bindgen(js_name = drain_events)]
+    pub fn js_drain_events(&mut self) -> js_sys::Uint32Array {
+        let v: Vec<u32> = self.drain_events().iter().flat_map(|e| e.parse_for_fe()).collect();
+        js_sys::Uint32Array::from(&v[..])
+    }
+}
+
+impl Game {
+    pub fn new(width: u32, height: u32, diff: DifficultyProfile) -> Result<Self, PacmanError> {
+        Self::from_config(GameConfig::new(width, height, diff))
     }
-}
\ No newline at end of file
+
+    pub fn from_config(config: GameConfig) -> Result<Self, PacmanError> {
+        config.validate()?;
+        let diff = config.diff();
+        let mut rng = config.seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
+        let wfc = config.maze_samples.as_deref().map(Wfc::from_ascii).transpose()?;
+        let level = Self::build_level(&config, wfc.as_ref(), 1, &mut rng)?;
+        if let Some(e) = config.pacman_spawn.and_then(|spawn| Self::spawn_error(&level, spawn)) {
+            return Err(e.into());
+        }
+        let pac_start_pos = Self::pacman_spawn(&config, &level);
+        let ghosts = Self::spawn_ghosts(&config, &level, diff, 1);
+
+        let mut pacman = Pacman::new(pac_start_pos, &diff);
+        pacman.set_turn_buffer(config.turn_buffer);
+        Ok(Self {
+            nav: NavGraph::new(&level),
+            dist: DistanceField::default(),
+            danger: DangerMap::default(),
+            rng,
+            wfc,
+            level,
+            ghosts,
+            pacman,
+            diff,
+            round: 1,
+            schedule: ModeSchedule::new(&diff, 1),
+            score: 0,
+            phase: Phase::Ready,
+            phase_ticks: config.ready_time,
+            events: Vec::new(),
+            ghost_combo: 0,
+            flashing: false,
+            fruit: None,
+            game_over: false,
+            config,
+        })
+    }
+
+    fn build_level(config: &GameConfig, wfc: Option<&Wfc>, round: u32, rng: &mut StdRng) -> Result<Level, PacmanError> {
+        let generator: &dyn MazeGenerator = match (config.maze_kind(round), wfc) {
+            (MazeKind::Wfc, Some(wfc)) => wfc,
+            (kind, _) => kind.generator(),
+        };
+        Level::generate(generator, rng, &config.maze())
+    }
+
+    // a custom spawn that doesn't fit a later maze falls back to the generated one
+    fn pacman_spawn(config: &GameConfig, level: &Level) -> Cord {
+        config.pacman_spawn
+            .filter(|spawn| Self::spawn_error(level, *spawn).is_none())
+            .unwrap_or(level.meta().pacman_spawn)
+    }
+
+    // why pacman can't start on `spawn` in this level, the house is checked before the dots
+    // because the gate cuts it off anyway
+    fn spawn_error(level: &Level, spawn: Cord) -> Option<ConfigError> {
+        if !Mover::Pacman.can_enter(level, spawn, Dir::None) { Some(ConfigError::SpawnBlocked(spawn)) }
+        else if level.meta().ghost_house.contains(spawn) { Some(ConfigError::SpawnInGhostHouse(spawn)) }
+        else if level.nearest_dot(spawn).is_none() { Some(ConfigError::SpawnUnreachable(spawn)) }
+        else { None }
+    }
+
+    // every ghost starts below the gate and scatters to the corner of its color
+    fn spawn_ghosts(config: &GameConfig, level: &Level, diff: DifficultyProfile, round: u32) -> Vec<Ghost> {
+        let meta = level.meta();
+        config.ghost_colors()
+            .enumerate()
+            .map(|(id, color)| {
+                let mut ghost = Ghost::new(id as u32, meta.ghost_spawn, meta.scatter_corners[color as usize - 1], color, diff);
+                ghost.set_round(round, level.total_dots());
+                ghost
+            })
+            .collect()
+    }
+
+    // generates the next maze of the config's list, score and lives are kept
+    pub fn next_level(&mut self) -> Result<(), PacmanError> {
+        let level = Self::build_level(&self.config, self.wfc.as_ref(), self.round + 1, &mut self.rng)?;
+        self.enter_level(level);
+        Ok(())
+    }
+
+    // the maze of the next round built step by step, e.g. as an intro or over several frames.
+    // Hand it to next_level_from once it is done
+    pub fn next_level_builder(&mut self) -> Result<LevelBuilder, PacmanError> {
+        let rng = StdRng::seed_from_u64(self.rng.next_u64());
+        let builder = LevelBuilder::new(self.config.maze_kind(self.round + 1), &self.config.maze(), rng)?;
+        Ok(match &self.wfc {
+            Some(wfc) => builder.with_wfc(wfc.clone()),
+            None => builder,
+        })
+    }
+
+    // the steps the builder has left are run first
+    pub fn next_level_from(&mut self, builder: LevelBuilder) -> Result<(), PacmanError> {
+        let level = builder.build()?;
+        self.enter_level(level);
+        Ok(())
+    }
+
+    fn enter_level(&mut self, level: Level) {
+        self.level = level;
+        self.nav = NavGraph::new(&self.level);
+        self.dist.invalidate();
+        self.round += 1;
+        self.schedule = ModeSchedule::new(&self.diff, self.round);
+        self.pacman.set_round(&self.diff, self.round);
+        self.fruit = None;
+        self.pacman.data.start_cord = Self::pacman_spawn(&self.config, &self.level);
+        self.pacman.reset();
+        self.ghosts = Self::spawn_ghosts(&self.config, &self.level, self.diff, self.round);
+        self.set_phase(Phase::Ready, self.config.ready_time);
+    }
+
+    pub fn tick(&mut self) -> Result<bool, PacmanError> {
+        if self.phase == Phase::Playing {
+            self.play_tick()?;
+            return Ok(self.game_over);
+        }
+        if self.phase_ticks > 0 {
+            self.phase_ticks -= 1;
+            return Ok(self.game_over);
+        }
+        match self.phase {
+            Phase::Ready => self.set_phase(Phase::Playing, 0),
+            Phase::Dying if self.pacman.revive() => {
+                self.reset_positions();
+                self.set_phase(Phase::Ready, self.config.ready_time);
+            },
+            Phase::Dying => {
+                self.game_over = true;
+                self.set_phase(Phase::GameOver, 0);
+            },
+            Phase::LevelComplete => self.next_level()?,
+            Phase::Playing | Phase::GameOver => (),
+        }
+        Ok(self.game_over)
+    }
+
+    pub fn config(&self) -> &GameConfig {
+        &self.config
+    }
+    pub fn level(&self) -> &Level {
+        &self.level
+    }
+    pub fn nav(&self) -> &NavGraph {
+        &self.nav
+    }
+    pub fn distances(&self) -> &DistanceField {
+        &self.dist
+    }
+    pub fn danger(&mut self) -> &DangerMap {
+        let ghosts: Vec<Cord> = self.ghosts.iter().filter(|ghost| !ghost.vulnerable()).map(|ghost| ghost.data.cord).collect();
+        self.danger.update(&self.level, &ghosts);
+        &self.danger
+    }
+    pub fn drain_events(&mut self) -> Vec<GameEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ghosts::GhostState;
+
+    const READY: u32 = 2;
+    const DYING: u32 = 3;
+    const COMPLETE: u32 = 2;
+
+    fn game(lives: u8) -> Game {
+        let config = GameConfig::new(21, 15, DifficultyProfile::normal()).lives(lives).timers(READY, DYING, COMPLETE).seed(7);
+        Game::from_config(config).unwrap()
+    }
+
+    fn run(game: &mut Game, ticks: u32) {
+        (0..ticks).for_each(|_| { game.tick().unwrap(); });
+    }
+
+    // the first ghost is put right onto pacman, he dies on the next tick
+    fn kill(game: &mut Game) {
+        let pacman = game.pacman.data;
+        let ghost = &mut game.ghosts[0].data;
+        (ghost.cord, ghost.prev_cord, ghost.sub, ghost.prev_sub) = (pacman.cord, pacman.cord, pacman.sub, pacman.sub);
+        game.tick().unwrap();
+        assert!(game.phase == Phase::Dying);
+    }
+
+    fn at_start(entity: &common::Entity) -> bool {
+        entity.cord == entity.start_cord && entity.sub == (0, 0) && entity.dir == Dir::None
+    }
+
+    #[test]
+    fn dying_puts_everyone_back_and_gets_ready_again() {
+        let mut game = game(3);
+        run(&mut game, READY);
+        assert!(game.phase == Phase::Ready);
+        run(&mut game, 1);
+        assert!(game.phase == Phase::Playing);
+        let cord = game.pacman.data.cord;
+        let dir = movement::DIRS.iter().copied().find(|dir| movement::can_move(&game.level, Mover::Pacman, cord, *dir)).unwrap();
+        game.pacman.set_dir_outside(dir);
+        run(&mut game, 20);
+        assert!(!at_start(&game.pacman.data));
+
+        kill(&mut game);
+        assert!(game.drain_events().contains(&GameEvent::PacmanDied));
+        run(&mut game, DYING);
+        assert!(game.phase == Phase::Dying && game.pacman.lives() == 3);
+        run(&mut game, 1);
+        assert!(game.phase == Phase::Ready && game.phase_ticks == READY);
+        assert_eq!(game.pacman.lives(), 2);
+        assert!(at_start(&game.pacman.data) && game.ghosts.iter().all(|ghost| at_start(&ghost.data)));
+        run(&mut game, READY + 1);
+        assert!(game.phase == Phase::Playing);
+    }
+
+    #[test]
+    fn a_ghost_left_on_the_spawn_cant_kill_pacman_again() {
+        let mut game = game(3);
+        run(&mut game, READY + 1);
+        kill(&mut game);
+        let spawn = game.pacman.data.start_cord;
+        game.ghosts.iter_mut().for_each(|ghost| (ghost.data.cord, ghost.data.prev_cord) = (spawn, spawn));
+        run(&mut game, DYING + 1);
+        assert!(game.phase == Phase::Ready);
+        assert!(game.ghosts.iter().all(|ghost| ghost.data.cord != spawn));
+        run(&mut game, READY + 1 + 10);
+        assert!(game.phase == Phase::Playing && game.pacman.lives() == 2);
+    }
+
+    #[test]
+    fn losing_the_last_life_ends_the_game() {
+        let mut game = game(1);
+        run(&mut game, READY + 1);
+        kill(&mut game);
+        run(&mut game, DYING);
+        assert!(game.phase == Phase::Dying);
+        assert!(game.tick().unwrap());
+        assert!(game.phase == Phase::GameOver && game.pacman.lives() == 0);
+        assert!(game.drain_events().contains(&GameEvent::GameOver));
+        assert!(game.tick().unwrap());
+        assert!(game.phase == Phase::GameOver);
+    }
+
+    #[test]
+    fn the_schedule_is_paused_while_ghosts_are_frightened() {
+        let mut game = game(3);
+        run(&mut game, READY + 1);
+        let elapsed = game.schedule.elapsed();
+        game.ghosts.iter_mut().for_each(|ghost| ghost.set_vulnerable());
+        run(&mut game, 10);
+        assert_eq!(game.schedule.elapsed(), elapsed);
+        game.ghosts.iter_mut().for_each(|ghost| ghost.reset());
+        run(&mut game, 10);
+        assert_eq!(game.schedule.elapsed(), elapsed + 10);
+    }
+
+    // a ghost right of the middle of a tile of the top corridor, heading right
+    fn ghost_heading_right(game: &mut Game) -> i64 {
+        let ghost = &mut game.ghosts[0].data;
+        (ghost.cord, ghost.prev_cord, ghost.sub, ghost.dir, ghost.prev_dir) = (Cord(1, 5), Cord(1, 5), (0, 2), Dir::Right, Dir::Right);
+        ghost.pos().1
+    }
+
+    #[test]
+    fn a_mode_switch_turns_the_ghosts_around() {
+        let mut game = game(3);
+        run(&mut game, READY + 1);
+        let before = ghost_heading_right(&mut game);
+        run(&mut game, 1);
+        assert!(game.ghosts[0].data.pos().1 > before);
+
+        let before = ghost_heading_right(&mut game);
+        assert!(game.schedule.mode() == GhostState::Scatter);
+        game.schedule.skip_to_switch();
+        run(&mut game, 1);
+        assert!(game.schedule.mode() == GhostState::Chase);
+        assert!(game.ghosts[0].data.pos().1 < before && game.ghosts[0].data.dir != Dir::Right);
+    }
+
+    #[test]
+    fn eating_the_last_dot_starts_the_next_round() {
+        let mut game = game(3);
+        run(&mut game, READY + 1);
+        let dots: Vec<Cord> = game.level.grid().cords()
+            .filter(|cord| matches!(game.level.block(*cord), BlockType::PacDot | BlockType::PowerPellet))
+            .collect();
+        for cord in &dots[1..] {
+            game.level.set_block(*cord, BlockType::Empty);
+            game.level.reduce_dot_count().unwrap();
+        }
+        (game.pacman.data.cord, game.pacman.data.prev_cord) = (dots[0], dots[0]);
+        game.tick().unwrap();
+        assert!(game.phase == Phase::LevelComplete && game.level.dot_count() == 0);
+        assert!(game.drain_events().contains(&GameEvent::LevelCleared));
+        let score = game.score;
+
+        run(&mut game, COMPLETE);
+        assert!(game.phase == Phase::LevelComplete && game.round == 1);
+        run(&mut game, 1);
+        assert!(game.phase == Phase::Ready && game.round == 2);
+        assert!(game.level.dot_count() > 0 && game.score == score && game.pacman.lives() == 3);
+        assert!(at_start(&game.pacman.data) && game.ghosts.iter().all(|ghost| at_start(&ghost.data)));
+    }
+}
@@ -0,0 +1,183 @@
+use crate::common::{BlockType, Cord, Dir, Entity, Level, SUBTILES};
+
+const HALF_TILE: i32 = SUBTILES / 2;
+// entities closer than this (in sub tile units) touch each other
+pub const COLLISION_DISTANCE: i64 = HALF_TILE as i64;
+
+pub const DIRS: [Dir; 4] = [Dir::Right, Dir::Down, Dir::Left, Dir::Up];
+
+// who is moving decides which tiles can be entered
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum Mover {
+    Pacman,
+    Ghost,
+}
+
+impl Mover {
+    // entering `cord` of the level by moving in `dir`
+    pub fn can_enter(self, level: &Level, cord: Cord, dir: Dir) -> bool {
+        match level.block(cord) {
+            BlockType::Wall => false,
+            // ghosts only pass the gate on their way out of the house
+            BlockType::Gate => self == Mover::Ghost && dir == level.meta().gate_exit(cord),
+            BlockType::Empty | BlockType::PacDot | BlockType::PowerPellet => true,
+        }
+    }
+}
+
+// the tile one step from `cord`, leaving the field on one side enters it on the opposite side (tunnels)
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct Step {
+    pub cord: Cord,
+    pub wrapped: bool,
+}
+
+pub fn neighbor(level: &Level, cord: Cord, dir: Dir) -> Option<Step> {
+    if let Some(next) = level.neighbor(cord, dir) { return Some(Step { cord: next, wrapped: false }); }
+    if dir == Dir::None || !level.in_bounds(cord) { return None; }
+    let (dy, dx) = dir.to_tup();
+    let wrap = |pos: u32, d: i32, size: u32| (pos as i64 + d as i64).rem_euclid(size as i64) as u32;
+    Some(Step { cord: Cord(wrap(cord.0, dy, level.height()), wrap(cord.1, dx, level.width())), wrapped: true })
+}
+
+// the tile reached by stepping in `dir`, None if the mover can't go there
+pub fn target(level: &Level, mover: Mover, cord: Cord, dir: Dir) -> Option<Step> {
+    neighbor(level, cord, dir).filter(|step| mover.can_enter(level, step.cord, dir))
+}
+
+pub fn can_move(level: &Level, mover: Mover, cord: Cord, dir: Dir) -> bool {
+    target(level, mover, cord, dir).is_some()
+}
+
+// the first passable dir out of `preferred` followed by the remaining dirs, turning around only in dead ends
+pub fn choose_dir(level: &Level, mover: Mover, cord: Cord, prev_dir: Dir, preferred: &[Dir]) -> Dir {
+    let back = prev_dir.get_opposite();
+    preferred.iter()
+        .chain(DIRS.iter())
+        .copied()
+        .find(|dir| (*dir != back || prev_dir == Dir::None) && can_move(level, mover, cord, *dir))
+        .or_else(|| Some(back).filter(|dir| can_move(level, mover, cord, *dir)))
+        .unwrap_or(Dir::None)
+}
+
+// tunnel tiles are the last TUNNEL_DEPTH tiles before an open border tile
+const TUNNEL_DEPTH: u32 = 2;
+
+pub fn in_tunnel(level: &Level, cord: Cord) -> bool {
+    let (h, w) = (level.height(), level.width());
+    let open = |c: Cord| level.block(c) != BlockType::Wall;
+    let row = (cord.1 < TUNNEL_DEPTH && open(Cord(cord.0, 0))) || (cord.1 >= w - TUNNEL_DEPTH && open(Cord(cord.0, w - 1)));
+    let col = (cord.0 < TUNNEL_DEPTH && open(Cord(0, cord.1))) || (cord.0 >= h - TUNNEL_DEPTH && open(Cord(h - 1, cord.1)));
+    open(cord) && (row || col)
+}
+
+pub fn at_center(data: &Entity) -> bool {
+    data.sub == (0, 0)
+}
+
+// sub tile units between the entity and the centre of its tile
+pub fn off_center(data: &Entity) -> i32 {
+    data.sub.0.abs() + data.sub.1.abs()
+}
+
+// drops the offset across `dir`, e.g. before turning back in the middle of a corner
+pub fn snap_to_axis(data: &mut Entity, dir: Dir) {
+    if dir.to_tup().0 != 0 { data.sub.1 = 0; } else { data.sub.0 = 0; }
+}
+
+// moves the entity one sub tile unit along its dir, an offset on the other axis
+// (left from cornering) is pulled back to the middle of the corridor
+pub fn sub_step(level: &Level, data: &mut Entity) {
+    let (dy, dx) = data.dir.to_tup();
+    if dy != 0 { data.sub.1 -= data.sub.1.signum(); } else { data.sub.0 -= data.sub.0.signum(); }
+    data.sub = (data.sub.0 + dy, data.sub.1 + dx);
+    if data.sub.0.abs() > HALF_TILE || data.sub.1.abs() > HALF_TILE {
+        if let Some(step) = neighbor(level, data.cord, data.dir) { data.cord = step.cord; }
+        data.sub = (data.sub.0 - dy * SUBTILES, data.sub.1 - dx * SUBTILES);
+    }
+    data.prev_dir = data.dir;
+}
+
+// chebyshev distance in sub tile units, measured through the tunnels if that is shorter
+pub fn distance(level: &Level, a: &Entity, b: &Entity) -> i64 {
+    let (pa, pb) = (a.pos(), b.pos());
+    let wrap = |d: i64, size: u32| d.abs().min(size as i64 * SUBTILES as i64 - d.abs());
+    wrap(pa.0 - pb.0, level.height()).max(wrap(pa.1 - pb.1, level.width()))
+}
+
+pub fn touching(level: &Level, a: &Entity, b: &Entity) -> bool {
+    distance(level, a, b) < COLLISION_DISTANCE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::Rect;
+
+    // a corridor with a dead end going down from (1, 3)
+    const DEAD_END: [&str; 5] = [
+        "#######",
+        "#.....#",
+        "###.###",
+        "###.###",
+        "#######",
+    ];
+
+    // a house below the corridor, its gate at (2, 4)
+    const HOUSE: [&str; 7] = [
+        "#########",
+        "#.......#",
+        "#.##-##.#",
+        "#.#hhh#.#",
+        "#.#####.#",
+        "#.......#",
+        "#########",
+    ];
+
+    #[test]
+    fn choose_dir_turns_back_only_in_dead_ends() {
+        let level = Level::from_rows(&DEAD_END);
+        let choose = |cord: Cord, prev_dir: Dir, preferred: &[Dir]| choose_dir(&level, Mover::Ghost, cord, prev_dir, preferred);
+        // the way back is skipped as long as there is another one
+        assert!(choose(Cord(2, 3), Dir::Down, &[Dir::Up]) == Dir::Down);
+        assert!(choose(Cord(1, 3), Dir::Right, &[Dir::Left]) == Dir::Right);
+        assert!(choose(Cord(1, 3), Dir::Right, &[Dir::Down]) == Dir::Down);
+        // at the end of the branch and in the corners of the corridor only the way back is left
+        assert!(choose(Cord(3, 3), Dir::Down, &[]) == Dir::Up);
+        assert!(choose(Cord(1, 1), Dir::Left, &[Dir::Down]) == Dir::Right);
+        assert!(choose(Cord(1, 5), Dir::Right, &[]) == Dir::Left);
+        // standing still any dir goes, the preferred one first
+        assert!(choose(Cord(1, 3), Dir::None, &[Dir::Left]) == Dir::Left);
+    }
+
+    #[test]
+    fn choose_dir_is_none_without_a_way_out() {
+        let level = Level::from_rows(&["#####", "##.##", "#####"]);
+        assert!(choose_dir(&level, Mover::Pacman, Cord(1, 2), Dir::Up, &[Dir::Up]) == Dir::None);
+    }
+
+    #[test]
+    fn gates_only_open_out_of_the_house() {
+        let level = Level::from_rows(&HOUSE);
+        let meta = level.meta();
+        let gate = meta.gates[0];
+        assert!(meta.gate_exit(gate) == Dir::Up);
+        assert!(Mover::Ghost.can_enter(&level, gate, Dir::Up));
+        assert!(!Mover::Ghost.can_enter(&level, gate, Dir::Down));
+        assert!(!Mover::Pacman.can_enter(&level, gate, Dir::Up));
+        assert!(can_move(&level, Mover::Ghost, Cord(3, 4), Dir::Up));
+        assert!(!can_move(&level, Mover::Ghost, Cord(1, 4), Dir::Down));
+        assert!(!can_move(&level, Mover::Pacman, Cord(3, 4), Dir::Up));
+        assert!(!can_move(&level, Mover::Pacman, Cord(1, 4), Dir::Down));
+        // a ghost in front of the gate walks past it instead of into the house
+        assert!(choose_dir(&level, Mover::Ghost, Cord(1, 4), Dir::Right, &[Dir::Down]) == Dir::Right);
+
+        // the exit follows the side of the house the gate is on
+        let house = |top: u32, left: u32| Rect::new(Cord(top, left), 3, 5);
+        let mut meta = meta.clone();
+        for (ghost_house, exit) in [(house(10, 8), Dir::Up), (house(8, 8), Dir::Down), (house(9, 10), Dir::Left), (house(9, 6), Dir::Right)] {
+            meta.ghost_house = ghost_house;
+            assert!(meta.gate_exit(Cord(10, 10)) == exit);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::{HashMap, HashSet};
+use wasm_bindgen::prelude::wasm_bindgen;
+use crate::common::{BlockType, Cord, Dir, Level};
+use crate::movement::{self, Mover, DIRS};
+
+#[derive(Clone)]
+pub struct NavNode {
+    pub cord: Cord,
+    // indices into NavGraph::edges, one per open direction
+    pub edges: Vec<usize>,
+}
+
+// a directed corridor between two nodes, the path excludes `from` and ends on `to`
+#[derive(Clone)]
+pub struct NavEdge {
+    pub from: usize,
+    pub to: usize,
+    pub dir: Dir,
+    pub length: u32,
+    pub path: Vec<Cord>,
+    pub through_gate: bool,
+    pub through_tunnel: bool,
+}
+
+// junctions and dead ends of a level connected by the corridors between them
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct NavGraph {
+    nodes: Vec<NavNode>,
+    edges: Vec<NavEdge>,
+    node_at: HashMap<Cord, usize>,
+}
+
+fn walkable(level: &Level, cord: Cord) -> bool {
+    level.block(cord) != BlockType::Wall
+}
+
+// the open tile next to `cord` and whether it was reached through a tunnel
+pub fn neighbor(level: &Level, cord: Cord, dir: Dir) -> Option<(Cord, bool)> {
+    movement::neighbor(level, cord, dir)
+        .filter(|step| walkable(level, step.cord))
+        .map(|step| (step.cord, step.wrapped))
+}
+
+fn open_dirs(level: &Level, cord: Cord) -> impl Iterator<Item = Dir> + '_ {
+    DIRS.iter().copied().filter(move |dir| neighbor(level, cord, *dir).is_some())
+}
+
+impl NavGraph {
+    pub fn new(level: &Level) -> Self {
+        let mut graph = NavGraph { nodes: Vec::new(), edges: Vec::new(), node_at: HashMap::new() };
+        for cord in level.grid().cords() {
+            if walkable(level, cord) && open_dirs(level, cord).count() != 2 {
+                graph.add_node(cord);
+            }
+        }
+        let mut traced = 0;
+        while traced < graph.nodes.len() {
+            graph.trace_edges(level, traced);
+            traced += 1;
+            // loops without any junction get a node on their first uncovered tile
+            if traced == graph.nodes.len() {
+                if let Some(cord) = graph.uncovered_tile(level) { graph.add_node(cord); }
+            }
+        }
+        graph
+    }
+
+    fn add_node(&mut self, cord: Cord) -> usize {
+        self.nodes.push(NavNode { cord, edges: Vec::new() });
+        self.node_at.insert(cord, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    fn trace_edges(&mut self, level: &Level, from: usize) {
+        let start = self.nodes[from].cord;
+        for dir in open_dirs(level, start).collect::<Vec<Dir>>() {
+            // a corridor that doesn't end on a node, e.g. at a broken seam gets no edge
+            let Some(edge) = self.trace(level, from, dir) else { continue; };
+            self.edges.push(edge);
+            let idx = self.edges.len() - 1;
+            self.nodes[from].edges.push(idx);
+        }
+    }
+
+    // follows the corridor leaving node `from` in `dir` up to the next node
+    fn trace(&self, level: &Level, from: usize, dir: Dir) -> Option<NavEdge> {
+        let (mut cord, mut curr_dir) = (self.nodes[from].cord, dir);
+        let (mut path, mut through_gate, mut through_tunnel) = (Vec::new(), false, false);
+        let limit = level.grid().as_slice().len();
+        let to = loop {
+            let (next, wrapped) = neighbor(level, cord, curr_dir)?;
+            // gates only open out of the house, the corridor leading in gets no edge
+            if level.block(next) == BlockType::Gate && !Mover::Ghost.can_enter(level, next, curr_dir) { return None; }
+            through_tunnel |= wrapped;
+            through_gate |= level.block(next) == BlockType::Gate;
+            path.push(next);
+            cord = next;
+            if let Some(node) = self.node_at.get(&cord) { break *node; }
+            if path.len() > limit { return None; }
+            curr_dir = open_dirs(level, cord).find(|d| *d != curr_dir.get_opposite())?;
+        };
+        Some(NavEdge { from, to, dir, length: path.len() as u32, path, through_gate, through_tunnel })
+    }
+
+    fn uncovered_tile(&self, level: &Level) -> Option<Cord> {
+        let covered: HashSet<Cord> = self.edges.iter().flat_map(|e| e.path.iter().copied()).collect();
+        level.grid().cords()
+            .find(|cord| walkable(level, *cord) && !covered.contains(cord) && !self.node_at.contains_key(cord))
+    }
+
+    pub fn nodes(&self) -> &[NavNode] {
+        &self.nodes
+    }
+    pub fn edges(&self) -> &[NavEdge] {
+        &self.edges
+    }
+    pub fn node_at(&self, cord: Cord) -> Option<usize> {
+        self.node_at.get(&cord).copied()
+    }
+    pub fn outgoing(&self, node: usize) -> impl Iterator<Item = &NavEdge> {
+        self.nodes[node].edges.iter().map(move |e| &self.edges[*e])
+    }
+}
+
+#[wasm_bindgen]
+impl NavGraph {
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn edge_count(&self) -> usize {
+        self.edges.len()
+    }
+    // [y, x] per node
+    #[wasm_bindgen(js_name = nodes)]
+    pub fn js_nodes(&self) -> js_sys::Uint32Array {
+        let v: Vec<u32> = self.nodes.iter().flat_map(|n| [n.cord.0, n.cord.1]).collect();
+        js_sys::Uint32Array::from(&v[..])
+    }
+    // [from, to, dir, length, through_gate, through_tunnel] per edge
+    #[wasm_bindgen(js_name = edges)]
+    pub fn js_edges(&self) -> js_sys::Uint32Array {
+        let v: Vec<u32> = self.edges.iter()
+            .flat_map(|e| [e.from as u32, e.to as u32, e.dir as u32, e.length, e.through_gate as u32, e.through_tunnel as u32])
+            .collect();
+        js_sys::Uint32Array::from(&v[..])
+    }
+    // [y, x] per tile of the edge, empty for an unknown edge
+    pub fn edge_path(&self, edge: usize) -> js_sys::Uint32Array {
+        let v: Vec<u32> = self.edges.get(edge).iter().flat_map(|e| e.path.iter()).flat_map(|c| [c.0, c.1]).collect();
+        js_sys::Uint32Array::from(&v[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edge(graph: &NavGraph, from: Cord, dir: Dir) -> Option<&NavEdge> {
+        graph.outgoing(graph.node_at(from)?).find(|edge| edge.dir == dir)
+    }
+
+    #[test]
+    fn nodes_sit_on_junctions_and_dead_ends() {
+        let level = Level::from_rows(&[
+            "#######",
+            "#.....#",
+            "###.###",
+            "###.###",
+            "#######",
+        ]);
+        let graph = NavGraph::new(&level);
+        let cords: Vec<Cord> = graph.nodes().iter().map(|node| node.cord).collect();
+        assert_eq!(cords, vec![Cord(1, 1), Cord(1, 3), Cord(1, 5), Cord(3, 3)]);
+        // every corridor is an edge each way
+        assert_eq!(graph.edge_count(), 6);
+        assert_eq!(graph.outgoing(graph.node_at(Cord(1, 3)).unwrap()).count(), 3);
+
+        let down = edge(&graph, Cord(1, 3), Dir::Down).unwrap();
+        assert_eq!((down.to, down.length, down.path.clone()), (graph.node_at(Cord(3, 3)).unwrap(), 2, vec![Cord(2, 3), Cord(3, 3)]));
+        let up = edge(&graph, Cord(3, 3), Dir::Up).unwrap();
+        assert_eq!((up.to, up.length, up.path.clone()), (graph.node_at(Cord(1, 3)).unwrap(), 2, vec![Cord(2, 3), Cord(1, 3)]));
+        assert!(graph.edges().iter().all(|edge| !edge.through_gate && !edge.through_tunnel));
+        assert!(edge(&graph, Cord(3, 3), Dir::Down).is_none() && graph.node_at(Cord(2, 3)).is_none());
+    }
+
+    #[test]
+    fn corridors_through_the_border_are_tunnels() {
+        let level = Level::from_rows(&[
+            "#######",
+            "#.....#",
+            "#.###.#",
+            "...#...",
+            "#######",
+        ]);
+        let graph = NavGraph::new(&level);
+        let tunnel = edge(&graph, Cord(3, 1), Dir::Left).unwrap();
+        assert_eq!((tunnel.path.clone(), tunnel.length), (vec![Cord(3, 0), Cord(3, 6), Cord(3, 5)], 3));
+        assert!(tunnel.through_tunnel && edge(&graph, Cord(3, 5), Dir::Right).unwrap().through_tunnel);
+        assert_eq!(graph.edges().iter().filter(|edge| edge.through_tunnel).count(), 2);
+    }
+
+    #[test]
+    fn gate_edges_only_lead_out_of_the_house() {
+        let level = Level::from_rows(&[
+            "#########",
+            "#.......#",
+            "#.##-##.#",
+            "#.#hhh#.#",
+            "#.#####.#",
+            "#.......#",
+            "#########",
+        ]);
+        let graph = NavGraph::new(&level);
+        let out = edge(&graph, Cord(3, 4), Dir::Up).unwrap();
+        assert!(out.through_gate && out.to == graph.node_at(Cord(1, 4)).unwrap());
+        assert_eq!(out.path, vec![Cord(2, 4), Cord(1, 4)]);
+        assert!(edge(&graph, Cord(1, 4), Dir::Down).is_none());
+        assert_eq!(graph.edges().iter().filter(|edge| edge.through_gate).count(), 1);
+    }
+}
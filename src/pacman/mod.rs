@@ -1,72 +1,189 @@
 
 
-use crate::{common::{Entity, Dir, Cord, BlockType, Level}, log};
+use crate::common::{BlockType, DifficultyProfile, Entity, Dir, Cord, Level};
+use crate::speed::SpeedTable;
+use crate::movement::{self, Mover};
 
 pub struct Pacman {
     lives: u8,
-    pub data: Entity
+    pub data: Entity,
+    // the last requested dir, kept until it can be taken or another key replaces it
+    queued_dir: Dir,
+    queued_at: u64,
+    // ticks after which a queued dir is dropped, 0 keeps it forever
+    turn_buffer: u32,
+    speeds: SpeedTable,
 }
 
+// how far (in sub tile units) before or after the centre of a tile pacman may take a corner
+const CORNER_WINDOW: i32 = 4;
+
 impl Pacman {
-    pub fn new(start_cord: Cord, lives: u8) -> Self {
+    pub fn new(start_cord: Cord, diff: &DifficultyProfile) -> Self {
+        let speeds = SpeedTable::new(diff, 1);
         Pacman { 
-            lives, 
-            data: Entity {
-                start_cord,
-                cord: start_cord,
-                prev_cord: start_cord,
-                dir: Dir::None,
-                prev_dir: Dir::None,
-                tick: 0,
-            }
+            lives: diff.lives, 
+            data: Entity::new(start_cord, speeds.pacman),
+            queued_dir: Dir::None,
+            queued_at: 0,
+            turn_buffer: 0,
+            speeds,
          }
     }
 
-    pub fn set_dir_outside(&mut self, dir: Dir) {
-        self.data.dir = dir;
+    pub fn set_round(&mut self, diff: &DifficultyProfile, round: u32) {
+        self.speeds = SpeedTable::new(diff, round);
     }
 
-    fn mv(&mut self, level: &Level){
-        let dir_valid = |cord: Cord, dir: &Dir|
-            level.block(cord + dir.to_tup()) != BlockType::Wall && level.block(cord + dir.to_tup()) != BlockType::Gate;
+    // pacman is slowed down by the dot he is about to eat
+    fn eating(&self, level: &Level) -> bool {
+        let has_dot = |cord: Cord| matches!(level.block(cord), BlockType::PacDot | BlockType::PowerPellet);
+        has_dot(self.data.cord) || movement::target(level, Mover::Pacman, self.data.cord, self.data.dir).is_some_and(|step| has_dot(step.cord))
+    }
 
+    pub fn set_dir_outside(&mut self, dir: Dir) {
+        if dir == Dir::None { return; }
+        self.queued_dir = dir;
+        self.queued_at = self.data.tick;
+    }
 
-        let adjusted_cord = if dir_valid(self.data.cord, &self.data.dir) {self.data.cord} else {self.data.prev_cord};
-        //log!("")
-        self.data.prev_cord = adjusted_cord;
-        self.data.cord = adjusted_cord + self.data.dir.to_tup();
-        self.data.prev_dir = self.data.dir;
+    pub fn set_turn_buffer(&mut self, ticks: u32) {
+        self.turn_buffer = ticks;
     }
 
     fn set_dir(&mut self, level: &Level) {
-        let dir_valid = |cord: Cord, dir: &Dir|
-            level.block(cord + dir.to_tup()) != BlockType::Wall && level.block(cord + dir.to_tup()) != BlockType::Gate;
+        let cord = self.data.cord;
+        let dir_valid = |dir: Dir| movement::can_move(level, Mover::Pacman, cord, dir);
+
+        let expired = self.turn_buffer > 0 && self.data.tick - self.queued_at > self.turn_buffer as u64;
+        if expired { self.queued_dir = Dir::None; }
 
-        if !dir_valid(self.data.cord, &self.data.dir) && !dir_valid(self.data.cord, &self.data.prev_dir){
+        let centered = movement::at_center(&self.data);
+        // turning around works anywhere, other turns only close to the centre of a tile
+        let reverse = !centered && self.queued_dir != Dir::None && self.queued_dir == self.data.dir.get_opposite();
+        let cornering = movement::off_center(&self.data) <= CORNER_WINDOW && dir_valid(self.queued_dir);
+        if reverse || cornering {
+            // right after a corner pacman is off the middle of the corridor, turning back from there would
+            // cut across the centre of the tile without looking for a wall, so he is put back on the axis
+            if reverse { movement::snap_to_axis(&mut self.data, self.queued_dir); }
+            self.data.dir = self.queued_dir;
+            self.queued_dir = Dir::None;
+        } else if centered && !dir_valid(self.data.dir) {
             self.data.dir = Dir::None;
-        } else if !dir_valid(self.data.cord, &self.data.dir) {
-            self.data.dir = self.data.prev_dir;
         }
     }
 
-    pub fn tick(&mut self, level: &Level) {
+    pub fn tick(&mut self, level: &Level, frightened: bool) {
         self.data.tick += 1;
-        self.set_dir(level);
-        self.mv(level);
+        self.data.begin_tick();
+        self.data.speed = self.speeds.pacman(self.eating(level), frightened);
+        for _ in 0..self.data.advance() {
+            self.set_dir(level);
+            if self.data.dir == Dir::None { break; }
+            movement::sub_step(level, &mut self.data);
+        }
     }
     pub fn revive(&mut self) -> bool {
         if self.lives == 1 { self.lives -= 1; return false;}
 		if self.lives < 1 { return false;}
-        self.data.cord = self.data.start_cord;
-        self.data.dir = Dir::None; self.data.prev_dir = Dir::None;
+        self.reset();
         self.lives -= 1;
         true
     }
+    pub fn reset(&mut self) {
+        self.data.reset();
+        self.queued_dir = Dir::None;
+    }
 
-    pub fn parse_for_fe(&self) -> [u32; 6]{
-        [self.data.cord.0, self.data.cord.1,self.data.prev_cord.0, self.data.prev_cord.1,self.data.prev_dir as u32, self.lives as u32]
+    pub fn parse_for_fe(&self) -> [i32; 8]{
+        [self.data.cord.0 as i32, self.data.cord.1 as i32,self.data.prev_cord.0 as i32, self.data.prev_cord.1 as i32,self.data.prev_dir as i32, self.lives as i32,
+            self.data.sub.0, self.data.sub.1]
+    }
+    pub fn gain_life(&mut self) {
+        self.lives = self.lives.saturating_add(1);
     }
     pub fn lives(&self) -> u8 {
         self.lives
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::SUBTILES;
+
+    // a corridor with a branch going up at (2, 3) and a wall below it
+    const JUNCTION: [&str; 5] = [
+        "#######",
+        "###.###",
+        "#P....#",
+        "#######",
+        "#######",
+    ];
+
+    fn pacman_at(cord: Cord, dir: Dir) -> Pacman {
+        let mut pacman = Pacman::new(cord, &DifficultyProfile::normal());
+        pacman.data.dir = dir;
+        pacman.data.prev_dir = dir;
+        pacman
+    }
+
+    // one sub tile unit of Pacman::tick
+    fn step(pacman: &mut Pacman, level: &Level) {
+        pacman.set_dir(level);
+        if pacman.data.dir != Dir::None { movement::sub_step(level, &mut pacman.data); }
+    }
+
+    #[test]
+    fn turning_back_after_a_corner_stops_at_the_wall() {
+        let level = Level::from_rows(&JUNCTION);
+        let mut pacman = pacman_at(Cord(2, 3), Dir::Right);
+        pacman.data.sub = (0, -3);
+        pacman.set_dir_outside(Dir::Up);
+        step(&mut pacman, &level);
+        assert!(pacman.data.dir == Dir::Up);
+
+        pacman.set_dir_outside(Dir::Down);
+        for _ in 0..SUBTILES * 2 {
+            step(&mut pacman, &level);
+            assert_ne!(level.block(pacman.data.cord), BlockType::Wall);
+        }
+        assert_eq!((pacman.data.cord, pacman.data.sub), (Cord(2, 3), (0, 0)));
+        assert!(pacman.data.dir == Dir::None);
+    }
+
+    // ticks pacman from (2, 1) to the right with `keys` pressed in order first, true once he went up the branch
+    fn takes_the_branch(turn_buffer: u32, keys: &[Dir]) -> bool {
+        let level = Level::from_rows(&JUNCTION);
+        let mut pacman = pacman_at(Cord(2, 1), Dir::Right);
+        pacman.set_turn_buffer(turn_buffer);
+        keys.iter().for_each(|key| pacman.set_dir_outside(*key));
+        for _ in 0..200 {
+            pacman.tick(&level, false);
+            if pacman.data.cord == Cord(1, 3) { return true; }
+        }
+        assert_eq!(pacman.data.cord, Cord(2, 5));
+        false
+    }
+
+    #[test]
+    fn an_early_turn_is_taken_at_the_junction() {
+        assert!(takes_the_branch(0, &[Dir::Up]));
+        let mut pacman = pacman_at(Cord(2, 2), Dir::Right);
+        pacman.set_dir_outside(Dir::Up);
+        step(&mut pacman, &Level::from_rows(&JUNCTION));
+        assert!(pacman.data.dir == Dir::Right && pacman.queued_dir == Dir::Up);
+    }
+
+    #[test]
+    fn a_newer_key_replaces_the_queued_turn() {
+        assert!(!takes_the_branch(0, &[Dir::Up, Dir::Down]));
+        assert!(takes_the_branch(0, &[Dir::Down, Dir::Up]));
+    }
+
+    #[test]
+    fn an_expired_turn_is_dropped() {
+        assert!(!takes_the_branch(4, &[Dir::Up]));
+        assert!(takes_the_branch(100, &[Dir::Up]));
+    }
+}
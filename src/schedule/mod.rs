@@ -0,0 +1,90 @@
+use crate::common::{DifficultyProfile, GhostAi};
+use crate::ghosts::GhostState;
+
+const FOREVER: u64 = u64::MAX;
+
+// the arcade scatter/chase cycle, shared by all ghosts and paused while they are frightened
+pub struct ModeSchedule {
+    phases: Vec<(GhostState, u64)>,
+    phase: usize,
+    tick: u64,
+}
+
+impl ModeSchedule {
+    pub fn new(diff: &DifficultyProfile, round: u32) -> Self {
+        let (scatter, chase) = (diff.scatter_time as u64, diff.chase_time as u64);
+        let short_scatter = scatter * 5 / 7;
+        let s = GhostState::Scatter;
+        let c = GhostState::Chase;
+        let phases = match round {
+            _ if diff.ghost_ai == GhostAi::Wander => vec![(s, FOREVER)],
+            1 => vec![(s, scatter), (c, chase), (s, scatter), (c, chase), (s, short_scatter), (c, FOREVER)],
+            2..=4 => vec![(s, scatter), (c, chase), (s, scatter), (c, chase), (s, short_scatter), (c, chase * 50), (s, 1), (c, FOREVER)],
+            _ => vec![(s, short_scatter), (c, chase), (s, short_scatter), (c, chase), (s, short_scatter), (c, chase * 50), (s, 1), (c, FOREVER)],
+        };
+        Self { phases, phase: 0, tick: 0 }
+    }
+
+    pub fn mode(&self) -> GhostState {
+        self.phases[self.phase].0
+    }
+
+    // returns true if the mode switched with this tick
+    pub fn tick(&mut self) -> bool {
+        self.tick += 1;
+        if self.tick < self.phases[self.phase].1 || self.phase + 1 == self.phases.len() { return false; }
+        self.tick = 0;
+        self.phase += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+impl ModeSchedule {
+    // ticks spent in the current phase
+    pub(crate) fn elapsed(&self) -> u64 {
+        self.tick
+    }
+
+    // the next tick switches the mode
+    pub(crate) fn skip_to_switch(&mut self) {
+        self.tick = self.phases[self.phase].1 - 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const S: GhostState = GhostState::Scatter;
+    const C: GhostState = GhostState::Chase;
+
+    #[test]
+    fn every_round_has_its_own_phases() {
+        let diff = DifficultyProfile::normal();
+        let (scatter, chase) = (diff.scatter_time as u64, diff.chase_time as u64);
+        let short = scatter * 5 / 7;
+        let phases = |round: u32| ModeSchedule::new(&diff, round).phases;
+        assert!(phases(1) == vec![(S, scatter), (C, chase), (S, scatter), (C, chase), (S, short), (C, FOREVER)]);
+        let later = vec![(S, scatter), (C, chase), (S, scatter), (C, chase), (S, short), (C, chase * 50), (S, 1), (C, FOREVER)];
+        assert!(phases(2) == later && phases(4) == later);
+        let fast = vec![(S, short), (C, chase), (S, short), (C, chase), (S, short), (C, chase * 50), (S, 1), (C, FOREVER)];
+        assert!(phases(5) == fast && phases(20) == fast);
+        // wandering ghosts never chase
+        assert!(ModeSchedule::new(&DifficultyProfile::easy(), 1).phases == vec![(S, FOREVER)]);
+    }
+
+    #[test]
+    fn tick_switches_after_each_phase_and_stays_in_the_last() {
+        let diff = DifficultyProfile::normal();
+        let mut schedule = ModeSchedule::new(&diff, 1);
+        assert!(schedule.mode() == S);
+        assert!((1..diff.scatter_time).all(|_| !schedule.tick()));
+        assert!(schedule.tick() && schedule.mode() == C);
+        assert!((1..diff.chase_time).all(|_| !schedule.tick()));
+        assert!(schedule.tick() && schedule.mode() == S);
+
+        schedule.phase = schedule.phases.len() - 1;
+        assert!((0..10_000).all(|_| !schedule.tick()) && schedule.mode() == C);
+    }
+}
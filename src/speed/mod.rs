@@ -0,0 +1,62 @@
+use crate::common::{DifficultyProfile, MAX_SPEED};
+
+// arcade speeds in percent, (last round, pacman, pacman eating, pacman frightened, pacman frightened eating,
+// ghost, ghost in tunnel, ghost frightened, elroy 1, elroy 2)
+const ARCADE_SPEEDS: [(u32, [u16; 9]); 4] = [
+    (1, [80, 71, 90, 79, 75, 40, 50, 80, 85]),
+    (4, [90, 79, 95, 83, 85, 45, 55, 90, 95]),
+    (20, [100, 87, 100, 87, 95, 50, 60, 100, 105]),
+    (u32::MAX, [90, 79, 90, 79, 95, 50, 60, 100, 105]),
+];
+// the difficulty speeds replace these first level values, everything else is scaled along
+const ARCADE_PACMAN_SPEED: u16 = 80;
+const ARCADE_GHOST_SPEED: u16 = 75;
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct SpeedTable {
+    pub pacman: u8,
+    pub pacman_eating: u8,
+    pub pacman_frightened: u8,
+    pub pacman_frightened_eating: u8,
+    pub ghost: u8,
+    pub ghost_tunnel: u8,
+    pub ghost_frightened: u8,
+    pub elroy: [u8; 2],
+}
+
+impl SpeedTable {
+    pub fn new(diff: &DifficultyProfile, round: u32) -> Self {
+        let (_, speeds) = ARCADE_SPEEDS.iter().find(|(r, _)| round <= *r).unwrap_or(&ARCADE_SPEEDS[3]);
+        let pacman = |i: usize| (speeds[i] * diff.pacman_speed as u16 / ARCADE_PACMAN_SPEED).clamp(1, MAX_SPEED as u16) as u8;
+        let ghost = |i: usize| (speeds[i] * diff.ghost_speed as u16 / ARCADE_GHOST_SPEED).clamp(1, MAX_SPEED as u16) as u8;
+        Self {
+            pacman: pacman(0),
+            pacman_eating: pacman(1),
+            pacman_frightened: pacman(2),
+            pacman_frightened_eating: pacman(3),
+            ghost: ghost(4),
+            ghost_tunnel: ghost(5),
+            ghost_frightened: ghost(6),
+            elroy: [ghost(7), ghost(8)],
+        }
+    }
+
+    pub fn pacman(&self, eating: bool, frightened: bool) -> u8 {
+        match (eating, frightened) {
+            (false, false) => self.pacman,
+            (true, false) => self.pacman_eating,
+            (false, true) => self.pacman_frightened,
+            (true, true) => self.pacman_frightened_eating,
+        }
+    }
+
+    // tunnels slow down every ghost, even elroy
+    pub fn ghost(&self, in_tunnel: bool, frightened: bool, elroy_stage: u8) -> u8 {
+        if in_tunnel { return self.ghost_tunnel; }
+        if frightened { return self.ghost_frightened; }
+        match elroy_stage {
+            0 => self.ghost,
+            x => self.elroy[(x as usize - 1).min(1)],
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use pacman::common::{BlockType, Cord, DifficultyProfile, Level};
+use pacman::grid::Grid;
+use pacman::nav::NavGraph;
+use pacman::level_gen::{GenStep, LevelBuilder, MazeConfig, MazeKind, Wfc};
+use pacman::config::{ConfigError, GameConfig};
+use pacman::error::PacmanError;
+use rand::{SeedableRng, rngs::StdRng};
+
+// landscape boards for desktops, portrait boards for phones and everything in between
+const WIDTHS: [u32; 6] = [13, 15, 21, 35, 61, 99];
+const HEIGHTS: [u32; 6] = [11, 15, 21, 35, 61, 99];
+const RUNS: usize = 3;
+
+fn walkable(block: BlockType) -> bool {
+    !matches!(block, BlockType::Wall | BlockType::Gate)
+}
+
+fn reachable_from(grid: &Grid<BlockType>, start: Cord) -> Grid<bool> {
+    let mut seen = Grid::new(grid.width(), grid.height(), false);
+    let mut stack = vec![start];
+    seen[start] = true;
+    while let Some(cord) = stack.pop() {
+        for (_, next) in grid.neighbors(cord) {
+            if !seen[next] && walkable(grid[next]) {
+                seen[next] = true;
+                stack.push(next);
+            }
+        }
+    }
+    seen
+}
+
+fn check_level(level: &Level, width: u32, height: u32) {
+    let (grid, meta) = (level.grid(), level.meta());
+    assert_eq!((grid.width(), grid.height()), (width, height));
+
+    let border = |c: Cord| c.0 == 0 || c.1 == 0 || c.0 == height - 1 || c.1 == width - 1;
+    assert!(grid.cords().filter(|c| border(*c)).all(|c| grid[c] == BlockType::Wall), "{}x{} border is open", width, height);
+
+    for pellet in [Cord(1, 1), Cord(1, width - 2), Cord(height - 2, 1), Cord(height - 2, width - 2)] {
+        assert_eq!(grid[pellet], BlockType::PowerPellet, "{}x{} pellet at {:?}", width, height, pellet);
+    }
+    assert_eq!(meta.gates.len(), 1);
+    assert_eq!(grid[Cord(meta.ghost_spawn.0 - 1, meta.ghost_spawn.1)], BlockType::Gate);
+    assert!(meta.ghost_house.contains(meta.ghost_spawn));
+
+    assert!(walkable(grid[meta.pacman_spawn]));
+    let seen = reachable_from(grid, meta.pacman_spawn);
+    let cut_off: Vec<Cord> = grid.cords()
+        .filter(|c| walkable(grid[*c]) && !seen[*c] && !meta.ghost_house.contains(*c))
+        .collect();
+    assert!(cut_off.is_empty(), "{}x{} has unreachable tiles {:?}", width, height, cut_off);
+    assert!(level.dot_count() > 0);
+
+    let squares: Vec<Cord> = grid.cords()
+        .filter(|c| c.0 + 1 < height && c.1 + 1 < width)
+        .filter(|c| [(0, 0), (0, 1), (1, 0), (1, 1)].iter().all(|off| walkable(grid[Cord(c.0 + off.0, c.1 + off.1)])))
+        .collect();
+    assert!(squares.is_empty(), "{}x{} has open 2x2 areas at {:?}", width, height, squares);
+
+    let nav = NavGraph::new(level);
+    assert!(nav.edges().iter().all(|e| e.to < nav.nodes().len() && e.path.last() == Some(&nav.nodes()[e.to].cord)));
+}
+
+// walkable tiles outside the ghost house with a single way out, the gate counts as one
+fn dead_ends(level: &Level) -> Vec<Cord> {
+    let (grid, meta) = (level.grid(), level.meta());
+    grid.cords()
+        .filter(|c| walkable(grid[*c]) && !meta.ghost_house.contains(*c))
+        .filter(|c| grid.neighbors(*c).filter(|(_, next)| grid[*next] != BlockType::Wall).count() == 1)
+        .collect()
+}
+
+#[test]
+fn every_size_generates_a_connected_maze() {
+    for width in WIDTHS {
+        for height in HEIGHTS {
+            for _ in 0..RUNS {
+                let level = Level::new(width, height, 0).unwrap();
+                check_level(&level, width, height);
+            }
+        }
+    }
+}
+
+#[test]
+fn every_generator_follows_the_rules() {
+    for kind in [MazeKind::Classic, MazeKind::Braided, MazeKind::Rooms, MazeKind::Wfc] {
+        for width in WIDTHS {
+            for height in HEIGHTS {
+                let config = MazeConfig::new(width, height, 0);
+                let level = Level::generate(kind.generator(), &mut StdRng::seed_from_u64(width as u64 * height as u64), &config).unwrap();
+                check_level(&level, width, height);
+            }
+        }
+    }
+}
+
+#[test]
+fn default_mazes_have_no_dead_ends() {
+    for kind in [MazeKind::Classic, MazeKind::Braided, MazeKind::Rooms, MazeKind::Wfc] {
+        for width in WIDTHS {
+            for height in HEIGHTS {
+                let config = MazeConfig::new(width, height, 0);
+                let level = Level::generate(kind.generator(), &mut StdRng::seed_from_u64(width as u64 + height as u64), &config).unwrap();
+                check_level(&level, width, height);
+                let dead = dead_ends(&level);
+                assert!(dead.is_empty(), "{:?} {}x{} has dead ends at {:?}", kind, width, height, dead);
+            }
+        }
+    }
+}
+
+#[test]
+fn dead_ends_can_be_allowed() {
+    let config = MazeConfig::new(61, 61, 0).dead_ends(100);
+    let kept = (0..RUNS as u64)
+        .map(|seed| Level::generate(MazeKind::Classic.generator(), &mut StdRng::seed_from_u64(seed), &config).unwrap())
+        .inspect(|level| check_level(level, 61, 61))
+        .map(|level| dead_ends(&level).len())
+        .sum::<usize>();
+    assert!(kept > 0);
+}
+
+#[test]
+fn the_same_seed_gives_the_same_maze() {
+    for kind in [MazeKind::Classic, MazeKind::Braided, MazeKind::Rooms, MazeKind::Wfc] {
+        let config = MazeConfig::new(41, 25, 2);
+        let a = Level::generate(kind.generator(), &mut StdRng::seed_from_u64(7), &config).unwrap();
+        let b = Level::generate(kind.generator(), &mut StdRng::seed_from_u64(7), &config).unwrap();
+        assert!(a.grid() == b.grid(), "{:?}", kind);
+    }
+}
+
+#[test]
+fn stepping_gives_the_same_maze_as_generate() {
+    for kind in [MazeKind::Classic, MazeKind::Braided, MazeKind::Rooms, MazeKind::Wfc] {
+        for (width, height) in [(13, 11), (41, 25), (62, 99)] {
+            let config = MazeConfig::new(width, height, 2);
+            let mut builder = LevelBuilder::new(kind, &config, StdRng::seed_from_u64(11)).unwrap();
+            let mut steps = Vec::new();
+            while !builder.is_done() {
+                steps.push(builder.step().unwrap());
+            }
+            assert_eq!(builder.step().unwrap(), GenStep::Done);
+            let shapes = steps.iter().filter(|step| matches!(step, GenStep::Rect | GenStep::Star)).count();
+            assert_eq!(shapes > 0, kind == MazeKind::Classic, "{:?} {:?}", kind, steps);
+            assert!(steps.ends_with(&[GenStep::Spawn, GenStep::PostProcessing, GenStep::Pellets, GenStep::Braid, GenStep::Tunnels]));
+
+            let stepped = builder.build().unwrap();
+            let level = Level::generate(kind.generator(), &mut StdRng::seed_from_u64(11), &config).unwrap();
+            assert!(stepped.grid() == level.grid() && stepped.meta() == level.meta(), "{:?} {}x{}", kind, width, height);
+        }
+    }
+}
+
+#[test]
+fn games_take_a_level_from_a_builder() {
+    let config = GameConfig::new(41, 31, DifficultyProfile::normal()).seed(5);
+    let mut game = pacman::Game::from_config(config).unwrap();
+    let mut builder = game.next_level_builder().unwrap();
+    assert_eq!(builder.kind(), game.config().maze_kind(2));
+    builder.step().unwrap();
+    game.next_level_from(builder).unwrap();
+    assert_eq!(game.round(), 2);
+    check_level(game.level(), 41, 31);
+    assert_eq!(game.field_at(1, 1), game.level().block(Cord(1, 1)));
+    assert_eq!((game.field_at(31, 0), game.field_at(0, 41), game.field_at(u32::MAX, u32::MAX)), (BlockType::Wall, BlockType::Wall, BlockType::Wall));
+}
+
+#[test]
+fn wfc_learns_from_custom_samples() {
+    let samples = "#########\n#.......#\n#.##.##.#\n#.......#\n#########\n\n#######\n#.....#\n#.#.#.#\n#.....#\n#######";
+    let wfc = Wfc::from_ascii(samples).unwrap();
+    for (width, height) in [(13, 11), (41, 31), (99, 61)] {
+        let level = Level::generate(&wfc, &mut StdRng::seed_from_u64(3), &MazeConfig::new(width, height, 0)).unwrap();
+        check_level(&level, width, height);
+    }
+    assert_eq!(Wfc::from_ascii("####\n###\n####").unwrap_err(), PacmanError::InvalidSample);
+    assert_eq!(Wfc::from_ascii("##\n##").unwrap_err(), PacmanError::InvalidSample);
+}
+
+#[test]
+fn extreme_aspect_ratios() {
+    for (width, height) in [(151, 11), (13, 151), (201, 15), (15, 201)] {
+        let level = Level::new(width, height, 0).unwrap();
+        check_level(&level, width, height);
+        for kind in [MazeKind::Braided, MazeKind::Rooms, MazeKind::Wfc] {
+            let level = Level::generate(kind.generator(), &mut StdRng::seed_from_u64(1), &MazeConfig::new(width, height, 0)).unwrap();
+            check_level(&level, width, height);
+        }
+    }
+}
+
+#[test]
+fn even_sizes_are_rounded_down() {
+    let level = Level::new(62, 16, 0).unwrap();
+    assert_eq!((level.width(), level.height()), (61, 15));
+}
+
+#[test]
+fn games_on_non_square_boards_place_entities_inside() {
+    for (width, height) in [(61, 15), (15, 61)] {
+        let game = pacman::Game::new(width, height, DifficultyProfile::normal()).unwrap();
+        let meta = game.level().meta();
+        assert!(game.level().in_bounds(meta.pacman_spawn));
+        assert!(game.level().in_bounds(meta.ghost_spawn));
+    }
+}
+
+#[test]
+fn small_sizes_are_raised_and_huge_ones_rejected() {
+    assert!(pacman::Game::new(12, 25, DifficultyProfile::normal()).is_ok());
+    assert!(pacman::Game::new(0, 0, DifficultyProfile::normal()).is_ok());
+    let huge = GameConfig::new(5000, 25, DifficultyProfile::normal());
+    assert!(matches!(pacman::Game::from_config(huge), Err(PacmanError::Config(ConfigError::InvalidSize { .. }))));
+}
+
+#[test]
+fn spawns_in_the_ghost_house_are_rejected() {
+    let config = GameConfig::new(25, 25, DifficultyProfile::normal()).seed(3);
+    let game = pacman::Game::from_config(config.clone()).unwrap();
+    let house = game.level().meta().ghost_spawn;
+    let result = pacman::Game::from_config(config.pacman_spawn(house.0, house.1));
+    assert!(matches!(result, Err(PacmanError::Config(ConfigError::SpawnInGhostHouse(c))) if c == house));
+}
+
+#[test]
+fn builders_check_their_size() {
+    let huge = LevelBuilder::new(MazeKind::Rooms, &MazeConfig::new(13, 5000, 0), StdRng::seed_from_u64(0));
+    assert!(matches!(huge, Err(PacmanError::Config(ConfigError::InvalidSize { width: 13, height: 5000 }))));
+
+    let builder = LevelBuilder::new(MazeKind::Rooms, &MazeConfig::new(0, 0, 0), StdRng::seed_from_u64(0)).unwrap();
+    assert_eq!((builder.width(), builder.height()), (13, 11));
+    assert_eq!(builder.field_at(11, 0), BlockType::Wall);
+    assert_eq!(builder.field_at(0, 13), BlockType::Wall);
+    assert_eq!(builder.field_at(u32::MAX, u32::MAX), BlockType::Wall);
+}
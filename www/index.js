@@ -1,19 +1,21 @@
-import {Game, Difficulty, BlockType, Dir} from "../pkg/pacman.js";
+import {Game, GameConfig, DifficultyProfile, BlockType, Dir, Phase, EventKind, ticks_per_second, subtiles} from "../pkg/pacman.js";
 import {memory} from "../pkg/pacman_bg.wasm";
 
 const HEIGHT = 25;
 const WIDTH = 25;
 const score = document.getElementById("score");
 const lives = document.getElementById("lives");
+const status = document.getElementById("status");
 
 export class PacManGame {
-    static tickRate = 5;
-    static renderRate = 20;
+    static tickRate = ticks_per_second();
+    static subtiles = subtiles();
     static blockSize = 50;
-    constructor(width, height, diff) {
-        this.width = width;
-        this.height = height;
-        this.game = Game.new(width,height,diff);
+    // throws if the config describes an impossible game
+    constructor(config) {
+        this.game = Game.from_config(config);
+        this.width = this.game.width();
+        this.height = this.game.height();
 
         const validKeys = [
                 "ArrowRight", "KeyD",
@@ -33,7 +35,7 @@ export class PacManGame {
 
 
     getGhosts() {
-        const positions = ["id","y","x","prev_y","prev_x","color","prev_dir","vulnerable"];
+        const positions = ["id","y","x","prev_y","prev_x","color","prev_dir","vulnerable","flashing","sub_y","sub_x"];
         let parsed = [];
         let ghosts = this.game.ghosts();
         for (let i = 0; i < ghosts.length; i += positions.length) {
@@ -47,7 +49,7 @@ export class PacManGame {
     }
 
     getPacman() {
-        const positions = ["y","x","prev_y","prev_x","prev_dir","lives"];
+        const positions = ["y","x","prev_y","prev_x","prev_dir","lives","sub_y","sub_x"];
         let p = this.game.pacman();
         let curr_obj = {};
         for (let j = 0; j < positions.length; ++j) 
@@ -86,9 +88,28 @@ export class PacManGame {
     getLives() {
         return this.game.lives();
     }
-    won() {
+    getPhase() {
+        return this.game.phase();
+    }
+    getFruit() {
+        const f = this.game.fruit();
+        return f.length == 0 ? undefined : {y: f[0], x: f[1], points: f[2]};
+    }
+    drainEvents() {
+        const positions = ["kind","id","points"];
+        let parsed = [];
+        let events = this.game.drain_events();
+        for (let i = 0; i < events.length; i += positions.length) {
+            let curr_obj = {};
+            for (let j = 0; j < positions.length; ++j) 
+                curr_obj[positions[j]] = events[i + j];
 
-        return this.game.dot_count() === BigInt(0);
+            parsed.push(curr_obj);
+        }
+        return parsed;
+    }
+    getRound() {
+        return this.game.round();
     }
 }
 
@@ -98,13 +119,11 @@ export class GameRenderer {
     static img = new Image();
     static spriteSize = 14;
     static canvas;
-    constructor(game, tickRate, renderRate) {
+    constructor(game) {
         this.ctx = GameRenderer.canvas.getContext("2d");
         this.ctx.fillStyle = "#FFFF00";
         this.ctx.fillRect(0,0,50,50);
         this.game = game;
-        this.tickRate = tickRate;
-        this.renderRate = renderRate;
         this.renderTick = 0;
 
         GameRenderer.img.src = "../static/pacman_sprites.png";
@@ -156,6 +175,7 @@ export class GameRenderer {
     }
     
     drawField() {
+        this.round = this.game.getRound();
         this.ctx.beginPath();
         const fieldptr = this.game.field();
         const field = new Uint8Array(memory.buffer, fieldptr, this.game.width * this.game.height);
@@ -182,69 +202,63 @@ export class GameRenderer {
         this.ctx.drawImage(GameRenderer.img, this.#getSpritePos(sox), this.#getSpritePos(soy), GameRenderer.spriteSize, GameRenderer.spriteSize, 0, 0, this.blockSize - 4, this.blockSize - 4);
         this.ctx.restore();
     }
-    #getTickAdjustedCord(obj, subtick) {
-        let off = subtick * (this.tickRate/this.renderRate);
-        let [off_y, off_x] = PacManGame.dirToTup(obj.prev_dir);
-        off_y *= off; off_x *= off;
-        return [obj.prev_y + off_y, obj.prev_x + off_x];
+    #getSubTileCord(obj) {
+        return [obj.y + obj.sub_y / PacManGame.subtiles, obj.x + obj.sub_x / PacManGame.subtiles];
     }
 
-    #drawPacman(subtick) {
-        let p = this.game.getPacman();
+    #animFrame() {
+        return Math.floor(this.renderTick / 4);
+    }
+
+    //clears an entity drawn at y,x and redraws the tiles it covered
+    #clearAt(y, x) {
+        this.ctx.clearRect(this.#getCtxPos(x) + 1, this.#getCtxPos(y) + 1, this.blockSize - 2, this.blockSize - 2);
+        const ys = new Set([Math.floor(y), Math.ceil(y)]);
+        const xs = new Set([Math.floor(x), Math.ceil(x)]);
+        ys.forEach((ty) => xs.forEach((tx) => {
+            if (ty >= 0 && tx >= 0 && ty < this.game.height && tx < this.game.width) this.#drawFieldAt(ty, tx);
+        }));
+    }
 
-        let sprite_offset = 2 - this.renderTick%3;
+    #drawPacman(p) {
+        let sprite_offset = 2 - this.#animFrame() % 3;
         let rotation = p.prev_dir * 90;
         if (p.prev_dir == Dir.None) { 
             sprite_offset = 2;
         }
 
-        let [y,x] = this.#getTickAdjustedCord(p, subtick);
-        if (this.prevPacX !== undefined && this.prevPacY !== undefined) 
-            this.ctx.clearRect(this.#getCtxPos(this.prevPacX) + 1, this.#getCtxPos(this.prevPacY) + 1, this.blockSize - 2, this.blockSize - 2);
-
+        let [y,x] = this.#getSubTileCord(p);
         this.#rotateAndPaintImg(0,sprite_offset,y,x,rotation);
-        this.prevPacX = x;
-        this.prevPacY = y;
-
-        let ghosts_behind = this.game.getGhosts().filter((ghost) => ghost.x == p.prev_x && ghost.prev_x != p.x && ghost.y == p.prev_y && ghost.prev_y != p.y);
-        ghosts_behind.forEach((ghost) => this.#drawGhost(ghost,subtick));
+        this.drawnAt.push([y, x]);
     }
 
-    #drawGhost(ghost, subtick) {
-        if (this.prevGhostPos[ghost.id] !== undefined){ 
-            let prevPos = this.prevGhostPos[ghost.id];
-            this.ctx.clearRect(this.#getCtxPos(prevPos.x) + 1, this.#getCtxPos(prevPos.y) + 1, this.blockSize - 2, this.blockSize - 2);
-        }
-
-        let p = this.game.getPacman();
-
-
-        this.#drawFieldAt(ghost.prev_y, ghost.prev_x);
-        this.#drawFieldAt(ghost.y, ghost.x);
-
+    #drawGhost(ghost) {
         let soy = ghost.vulnerable ? 5 : ghost.color;
-        let sox = +(this.renderTick % 2 == 0) ;
-        sox += ghost.vulnerable ? 0 : ghost.prev_dir == Dir.None ? 6 : ghost.prev_dir * 2;
+        let sox = +(this.#animFrame() % 2 == 0) ;
+        sox += ghost.vulnerable ? (ghost.flashing && this.#animFrame() % 4 < 2 ? 2 : 0) : ghost.prev_dir == Dir.None ? 6 : ghost.prev_dir * 2;
 
-        let [y,x] = this.#getTickAdjustedCord(ghost, subtick);
-        this.prevGhostPos[ghost.id] = {"y": y, "x": x};
+        let [y,x] = this.#getSubTileCord(ghost);
         this.#paintImg(soy,sox,y, x);
-
-        //if pacman was behin the ghost he would be hidden by the above clearRect call
-        if (p.y == ghost.prev_y && p.x == ghost.prev_x) this.#drawPacman(subtick);
-        let ghosts_behind = this.game.getGhosts().filter((g) => g.x == ghost.prev_x && g.prev_x != ghost.x && g.y == ghost.prev_y && g.prev_y != g.prev_x);
-        ghosts_behind.forEach((ghost) => this.#drawGhost(ghost,subtick));
+        this.drawnAt.push([y, x]);
     }
 
-    #drawGhosts(subtick) {
-        if (this.prevGhostPos === undefined) this.prevGhostPos = {};
-        this.game.getGhosts().forEach((ghost) => this.#drawGhost(ghost, subtick));
+    #drawFruit(fruit) {
+        const halfBlock = this.blockSize / 2;
+        this.ctx.beginPath();
+        this.ctx.fillStyle = "#FF0000";
+        this.ctx.arc(this.#getCtxPos(fruit.x) + halfBlock, this.#getCtxPos(fruit.y) + halfBlock, this.blockSize / 3, 0, Math.PI * 2);
+        this.ctx.fill();
+        this.drawnAt.push([fruit.y, fruit.x]);
     }
 
-    renderEntities(subtick) {
+    renderEntities() {
         ++this.renderTick;
-        this.#drawPacman(subtick);
-        this.#drawGhosts(subtick);
+        (this.drawnAt || []).forEach(([y, x]) => this.#clearAt(y, x));
+        this.drawnAt = [];
+        const fruit = this.game.getFruit();
+        if (fruit !== undefined) this.#drawFruit(fruit);
+        this.#drawPacman(this.game.getPacman());
+        this.game.getGhosts().forEach((ghost) => this.#drawGhost(ghost));
     }
 
     clearAll() {
@@ -253,42 +267,55 @@ export class GameRenderer {
 }
 
 
-function timeout(ms) {
-    return new Promise(resolve => setTimeout(resolve, ms));
-}
-
-let totalScore = 0;
+const statusText = {
+    [Phase.Ready]: "READY!",
+    [Phase.Playing]: "",
+    [Phase.Dying]: "",
+    [Phase.LevelComplete]: "LEVEL CLEARED",
+    [Phase.GameOver]: "GAME OVER",
+};
 let currentGame;
 let currentRenderer;
+let lastFrame;
+let popupUntil = [0, ""];
+let tickTime = 0;
 GameRenderer.canvas = document.getElementById("game-canvas");
-async function tick() {
-    let gameover = currentGame.game.tick();
-    score.innerText = "Score: " + (currentGame.getScore() + totalScore);
-    lives.innerText = "Lives: " + currentGame.getLives();
-
-    for(let subtick = 1; subtick <= PacManGame.renderRate / PacManGame.tickRate; ++subtick) {
-        await timeout(1000 / PacManGame.renderRate);
-        currentRenderer.renderEntities(subtick);
+function frame(timestamp) {
+    //the game runs at a fixed tick rate, independent of the display refresh rate
+    tickTime = Math.min(tickTime + timestamp - (lastFrame ?? timestamp), 250);
+    lastFrame = timestamp;
+    while (tickTime >= 1000 / PacManGame.tickRate) {
+        currentGame.game.tick();
+        tickTime -= 1000 / PacManGame.tickRate;
     }
-
-    if (gameover) {
-        alert("GAMEOVER!!!!!");
-        return;
-    }
-    if (currentGame.won()) {
-        totalScore += currentGame.getScore();
-        startNewGame();
-        return;
+    if (currentGame.getRound() !== currentRenderer.round) {
+        currentRenderer.clearAll();
+        currentRenderer.drawField();
     }
-    requestAnimationFrame(tick);
+    score.innerText = "Score: " + currentGame.getScore();
+    lives.innerText = "Lives: " + currentGame.getLives();
+    currentGame.drainEvents().forEach((event) => {
+        if (event.kind == EventKind.GhostEaten || event.kind == EventKind.FruitEaten)
+            popupUntil = [timestamp + 1000, "+" + event.points];
+    });
+    status.innerText = timestamp < popupUntil[0] ? popupUntil[1] : statusText[currentGame.getPhase()];
+    currentRenderer.renderEntities();
+
+    if (currentGame.getPhase() !== Phase.GameOver)
+        requestAnimationFrame(frame);
 }
 
 const startNewGame = () => {
-    currentGame = new PacManGame(WIDTH,HEIGHT,Difficulty.Normal);
-    currentRenderer = new GameRenderer(currentGame,PacManGame.tickRate,PacManGame.renderRate);
+    try {
+        currentGame = new PacManGame(new GameConfig(WIDTH,HEIGHT,DifficultyProfile.normal()).tunnels(2));
+    } catch (e) {
+        status.innerText = e.message;
+        return;
+    }
+    currentRenderer = new GameRenderer(currentGame);
     currentRenderer.clearAll();
     currentRenderer.drawField();
-    requestAnimationFrame(tick);
+    requestAnimationFrame(frame);
 }
 
 startNewGame();